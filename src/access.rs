@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::header::{HeaderName, AUTHORIZATION};
+use axum::http::HeaderMap;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use password_hash::rand_core::OsRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::task::{self, JoinError};
+use tracing::warn;
+
+use crate::{ChannelCategory, ChannelList};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid password hash for user {0:?}: {1}")]
+    InvalidPasswordHash(String, argon2::password_hash::Error),
+    #[error("invalid proxy header name {0:?}")]
+    InvalidProxyHeader(String),
+    #[error("access rule must name a category, a channel or both")]
+    EmptyRule,
+    #[error("hashing password: {0}")]
+    HashPassword(argon2::password_hash::Error),
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    #[error("verifying password: {0}")]
+    VerifyPassword(JoinError),
+}
+
+/// The `[auth]` section of the configuration.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Users that can log in with HTTP basic authentication.
+    pub users: Vec<UserConfig>,
+    /// Header containing the name of the user authenticated by a reverse proxy.
+    ///
+    /// The proxy must strip this header from the requests it forwards, as
+    /// anyone could otherwise impersonate any user.
+    pub proxy_header: Option<String>,
+    /// Groups of users, referred to as `@group` in access rules.
+    pub groups: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub name: String,
+    /// Argon2 hash of the password in PHC format, as output by `amardiscord
+    /// hash-password`.
+    pub password_hash: String,
+}
+
+/// Restricts the channels of a category, a channel, or a channel of a
+/// category, to some users.
+///
+/// If several rules match a channel, a user must be allowed by all of them to
+/// view it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    pub category: Option<String>,
+    pub channel: Option<String>,
+    /// User names, `@group` names, or `*` for any authenticated user.
+    pub allow: Vec<String>,
+}
+
+/// Hashes a password for the `[[auth.users]]` section of the configuration.
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(Error::HashPassword)?
+        .to_string())
+}
+
+/// Authenticates visitors and decides which channels they can view.
+#[derive(Default)]
+pub struct AccessControl {
+    users: HashMap<String, String>,
+    proxy_header: Option<HeaderName>,
+    groups: HashMap<String, Vec<String>>,
+    // For each restricted channel, the allow list of every rule matching it.
+    restricted: HashMap<u64, Vec<Vec<String>>>,
+    // Digests of the passwords that were successfully verified, so that the
+    // expensive hash is only computed once per user.
+    verified: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl AccessControl {
+    /// Resolves the access rules against the channels of the archive.
+    pub fn new(
+        auth: &AuthConfig,
+        rules: &[AccessRule],
+        channel_list: &ChannelList,
+    ) -> Result<Self, Error> {
+        let mut users = HashMap::new();
+        for user in &auth.users {
+            PasswordHash::new(&user.password_hash)
+                .map_err(|e| Error::InvalidPasswordHash(user.name.clone(), e))?;
+            users.insert(user.name.clone(), user.password_hash.clone());
+        }
+
+        let proxy_header = auth
+            .proxy_header
+            .as_ref()
+            .map(|name| {
+                HeaderName::try_from(name.as_str())
+                    .map_err(|_| Error::InvalidProxyHeader(name.clone()))
+            })
+            .transpose()?;
+
+        let mut restricted: HashMap<u64, Vec<Vec<String>>> = HashMap::new();
+        for rule in rules {
+            if rule.category.is_none() && rule.channel.is_none() {
+                return Err(Error::EmptyRule);
+            }
+
+            let mut matched = false;
+            for ChannelCategory { name: category, channels } in &channel_list.categories {
+                if rule.category.as_ref().is_some_and(|name| name != category) {
+                    continue;
+                }
+
+                for channel in channels {
+                    if rule.channel.as_ref().is_none_or(|name| *name == channel.name) {
+                        restricted.entry(channel.id).or_default().push(rule.allow.clone());
+                        matched = true;
+                    }
+                }
+            }
+
+            if !matched {
+                warn!(
+                    "Access rule for category {:?} and channel {:?} matches no channel.",
+                    rule.category, rule.channel
+                );
+            }
+        }
+
+        Ok(Self {
+            users,
+            proxy_header,
+            groups: auth.groups.clone(),
+            restricted,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Identifies the visitor from the request headers.
+    ///
+    /// Requests without credentials are anonymous. Requests with invalid
+    /// credentials are rejected.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Viewer, Error> {
+        let name =
+            if let Some(name) = self.proxy_header.as_ref().and_then(|header| headers.get(header)) {
+                Some(name.to_str().map_err(|_| Error::InvalidCredentials)?.to_string())
+            } else if let Some(authorization) = headers.get(AUTHORIZATION) {
+                Some(self.verify_basic(authorization.as_bytes()).await?)
+            } else {
+                None
+            };
+
+        Ok(self.viewer(name))
+    }
+
+    /// A visitor who didn't log in.
+    pub fn anonymous(&self) -> Viewer {
+        self.viewer(None)
+    }
+
+    fn viewer(&self, name: Option<String>) -> Viewer {
+        let hidden_channels = self
+            .restricted
+            .iter()
+            .filter(|(_, rules)| {
+                !rules.iter().all(|allow| allow.iter().any(|p| self.matches(p, name.as_deref())))
+            })
+            .map(|(&channel_id, _)| channel_id)
+            .collect();
+
+        Viewer { name, hidden_channels }
+    }
+
+    /// Whether the viewer is one of `allow`, a list of user names, `@group`
+    /// names, or `*` for any authenticated user.
+    pub fn allows(&self, allow: &[String], viewer: &Viewer) -> bool {
+        allow.iter().any(|principal| self.matches(principal, viewer.name.as_deref()))
+    }
+
+    fn matches(&self, principal: &str, name: Option<&str>) -> bool {
+        let Some(name) = name else {
+            return false;
+        };
+
+        if principal == "*" {
+            true
+        } else if let Some(group) = principal.strip_prefix('@') {
+            self.groups.get(group).is_some_and(|members| members.iter().any(|m| m == name))
+        } else {
+            principal == name
+        }
+    }
+
+    // Verifies `Basic` credentials, returning the user name.
+    //
+    // The password hash is computed on a blocking thread, without holding the
+    // cache of verified passwords, as it takes a while by design.
+    async fn verify_basic(&self, authorization: &[u8]) -> Result<String, Error> {
+        let credentials = authorization
+            .strip_prefix(b"Basic ")
+            .and_then(|encoded| BASE64_STANDARD.decode(encoded).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(Error::InvalidCredentials)?;
+        let (name, password) = credentials.split_once(':').ok_or(Error::InvalidCredentials)?;
+
+        let hash = self.users.get(name).ok_or(Error::InvalidCredentials)?.clone();
+        let digest = Sha256::digest(password.as_bytes()).to_vec();
+
+        if self.verified().get(name) == Some(&digest) {
+            return Ok(name.to_string());
+        }
+
+        let password = password.to_string();
+        task::spawn_blocking(move || {
+            let hash = PasswordHash::new(&hash).map_err(|_| Error::InvalidCredentials)?;
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .map_err(|_| Error::InvalidCredentials)
+        })
+        .await
+        .map_err(Error::VerifyPassword)??;
+
+        self.verified().insert(name.to_string(), digest);
+
+        Ok(name.to_string())
+    }
+
+    fn verified(&self) -> MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.verified.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A visitor, and the channels hidden from them.
+pub struct Viewer {
+    pub name: Option<String>,
+    hidden_channels: HashSet<u64>,
+}
+
+impl Viewer {
+    pub fn can_view(&self, channel_id: u64) -> bool {
+        !self.hidden_channels.contains(&channel_id)
+    }
+
+    /// Removes the channels hidden from the viewer, and the categories left
+    /// empty.
+    pub fn filter_channel_list(&self, mut channel_list: ChannelList) -> ChannelList {
+        for category in &mut channel_list.categories {
+            category.channels.retain(|channel| self.can_view(channel.id));
+        }
+        channel_list.categories.retain(|category| !category.channels.is_empty());
+        channel_list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChannelListEntry;
+
+    fn channel_list() -> ChannelList {
+        let channel =
+            |id, name: &str| ChannelListEntry { name: name.to_string(), id, channel_type: 0 };
+
+        ChannelList {
+            categories: vec![
+                ChannelCategory {
+                    name: "General".to_string(),
+                    channels: vec![channel(1, "general"), channel(2, "secret")],
+                },
+                ChannelCategory {
+                    name: "Moderation".to_string(),
+                    channels: vec![channel(3, "mod-chat"), channel(4, "secret")],
+                },
+            ],
+        }
+    }
+
+    fn basic(name: &str, password: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let credentials = BASE64_STANDARD.encode(format!("{name}:{password}"));
+        headers.insert(AUTHORIZATION, format!("Basic {credentials}").parse().unwrap());
+        headers
+    }
+
+    fn access_control() -> AccessControl {
+        let auth = AuthConfig {
+            users: vec![
+                UserConfig {
+                    name: "alice".to_string(),
+                    password_hash: hash_password("a").unwrap(),
+                },
+                UserConfig { name: "bob".to_string(), password_hash: hash_password("b").unwrap() },
+            ],
+            proxy_header: Some("X-Forwarded-User".to_string()),
+            groups: HashMap::from([("mods".to_string(), vec!["alice".to_string()])]),
+        };
+        let rules = [
+            AccessRule {
+                category: Some("Moderation".to_string()),
+                channel: None,
+                allow: vec!["@mods".to_string()],
+            },
+            AccessRule {
+                category: None,
+                channel: Some("secret".to_string()),
+                allow: vec!["*".to_string()],
+            },
+        ];
+
+        AccessControl::new(&auth, &rules, &channel_list()).unwrap()
+    }
+
+    fn visible_channels(viewer: &Viewer) -> Vec<u64> {
+        (1..=4).filter(|&id| viewer.can_view(id)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_access_rules() {
+        let access = access_control();
+
+        let anonymous = access.authenticate(&HeaderMap::new()).await.unwrap();
+        assert_eq!(visible_channels(&anonymous), [1]);
+
+        let bob = access.authenticate(&basic("bob", "b")).await.unwrap();
+        assert_eq!(visible_channels(&bob), [1, 2]);
+
+        let alice = access.authenticate(&basic("alice", "a")).await.unwrap();
+        assert_eq!(visible_channels(&alice), [1, 2, 3, 4]);
+        // Verified credentials are cached.
+        assert!(access.authenticate(&basic("alice", "a")).await.is_ok());
+
+        let editors = ["@mods".to_string()];
+        assert!(access.allows(&editors, &alice));
+        assert!(!access.allows(&editors, &bob));
+        assert!(!access.allows(&["*".to_string()], &anonymous));
+
+        let filtered = anonymous.filter_channel_list(channel_list());
+        assert_eq!(filtered.categories.len(), 1);
+        assert_eq!(filtered.categories[0].channels.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let access = access_control();
+
+        assert!(access.authenticate(&basic("alice", "b")).await.is_err());
+        assert!(access.authenticate(&basic("carol", "c")).await.is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-User", "alice".parse().unwrap());
+        let alice = access.authenticate(&headers).await.unwrap();
+        assert_eq!(alice.name.as_deref(), Some("alice"));
+        assert_eq!(visible_channels(&alice), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{OptionalExtension, Row, ToSql};
+use serde::{Deserialize, Serialize};
+
+pub use crate::side_db::Error;
+use crate::{side_db, MessageKey, ANNOTATIONS_PATH};
+
+/// Maximum length of an annotation, in characters.
+pub const MAX_CONTENT_LENGTH: usize = 5000;
+
+/// Maximum number of annotated messages found by a search.
+const MAX_SEARCH_RESULTS: u64 = 1000;
+
+// The full-text search index of the annotations is kept up to date by
+// triggers.
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS annotations (
+        annotation_id INTEGER PRIMARY KEY,
+        channel_name TEXT NOT NULL,
+        sent_at TEXT NOT NULL,
+        username TEXT NOT NULL,
+        author TEXT NOT NULL,
+        content TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS annotations_messages
+    ON annotations(channel_name, sent_at, username);
+
+    CREATE VIRTUAL TABLE IF NOT EXISTS annotations_fts
+    USING FTS5(content, content='annotations', content_rowid='annotation_id');
+
+    CREATE TRIGGER IF NOT EXISTS annotations_insert AFTER INSERT ON annotations BEGIN
+        INSERT INTO annotations_fts (rowid, content)
+        VALUES (new.annotation_id, new.content);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS annotations_delete AFTER DELETE ON annotations BEGIN
+        INSERT INTO annotations_fts (annotations_fts, rowid, content)
+        VALUES ('delete', old.annotation_id, old.content);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS annotations_update AFTER UPDATE ON annotations BEGIN
+        INSERT INTO annotations_fts (annotations_fts, rowid, content)
+        VALUES ('delete', old.annotation_id, old.content);
+        INSERT INTO annotations_fts (rowid, content)
+        VALUES (new.annotation_id, new.content);
+    END;
+"#;
+
+/// The `[annotations]` section of the configuration.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnnotationsConfig {
+    /// Database holding the annotations, which editors change from the
+    /// message pages.
+    pub path: PathBuf,
+    /// Users who can annotate messages, as user names, `@group` names, or `*`
+    /// for any authenticated user.
+    pub editors: Vec<String>,
+}
+
+impl Default for AnnotationsConfig {
+    fn default() -> Self {
+        Self { path: PathBuf::from(ANNOTATIONS_PATH), editors: Vec::new() }
+    }
+}
+
+/// A note attached to a message of the archive by an editor.
+#[derive(Serialize, Debug, Clone)]
+pub struct Annotation {
+    pub id: u64,
+    /// The message, which keeps its annotations when the archive is rebuilt.
+    pub message: MessageKey,
+    pub author: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Columns read by [`Annotation::from_row`].
+const COLUMNS: &str =
+    "annotation_id, channel_name, sent_at, username, author, content, created_at, updated_at";
+
+impl Annotation {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let message =
+            MessageKey { channel: row.get(1)?, sent_at: row.get(2)?, username: row.get(3)? };
+
+        Ok(Self {
+            id: row.get(0)?,
+            message,
+            author: row.get(4)?,
+            content: row.get(5)?,
+            created_at: row.get(6)?,
+            updated_at: row.get(7)?,
+        })
+    }
+}
+
+/// Body of the requests adding or editing an annotation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationContent {
+    pub content: String,
+}
+
+/// The annotations of the messages of a page, by the ids of the messages.
+#[derive(Default)]
+pub struct MessageAnnotations {
+    by_message: HashMap<u64, Vec<Annotation>>,
+    /// Whether the viewer can add, edit and delete annotations.
+    pub editable: bool,
+}
+
+impl MessageAnnotations {
+    /// Groups annotations by the ids of the messages of the page, given by
+    /// `rowids`.
+    pub fn new(
+        annotations: Vec<Annotation>,
+        rowids: &HashMap<MessageKey, u64>,
+        editable: bool,
+    ) -> Self {
+        let mut by_message: HashMap<u64, Vec<Annotation>> = HashMap::new();
+        for annotation in annotations {
+            if let Some(&rowid) = rowids.get(&annotation.message) {
+                by_message.entry(rowid).or_default().push(annotation);
+            }
+        }
+
+        Self { by_message, editable }
+    }
+
+    /// The annotations of a message, the oldest first.
+    pub fn of(&self, message: &u64) -> &[Annotation] {
+        self.by_message.get(message).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Checks the content of an annotation, returning it without surrounding
+/// spaces.
+pub fn check_content(content: &str) -> Result<&str, Error> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(Error::Invalid("Annotations can't be empty.".to_string()));
+    }
+    if content.chars().count() > MAX_CONTENT_LENGTH {
+        return Err(Error::Invalid(format!(
+            "Annotations can't be longer than {MAX_CONTENT_LENGTH} characters."
+        )));
+    }
+
+    Ok(content)
+}
+
+/// The notes of editors on messages of the archive, searchable along with the
+/// messages they annotate.
+pub struct Annotations(Pool<SqliteConnectionManager>);
+
+impl Annotations {
+    /// Opens the database of annotations, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self(side_db::open(path, SCHEMA)?))
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Annotation>, Error> {
+        let annotation = self
+            .0
+            .get()?
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM annotations WHERE annotation_id = ?1"),
+                [id],
+                Annotation::from_row,
+            )
+            .optional()?;
+
+        Ok(annotation)
+    }
+
+    /// Retrieves the annotations of some messages, the oldest first.
+    pub fn for_messages(&self, messages: &[MessageKey]) -> Result<Vec<Annotation>, Error> {
+        if messages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let db = self.0.get()?;
+        let placeholders = vec!["(?, ?, ?)"; messages.len()].join(", ");
+        let params = messages
+            .iter()
+            .flat_map(|key| [&key.channel as &dyn ToSql, &key.sent_at, &key.username]);
+
+        let annotations = db
+            .prepare(&format!(
+                r#"
+                SELECT {COLUMNS}
+                FROM annotations
+                WHERE (channel_name, sent_at, username) IN (VALUES {placeholders})
+                ORDER BY created_at, annotation_id
+                "#
+            ))?
+            .query_map(rusqlite::params_from_iter(params), Annotation::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(annotations)
+    }
+
+    /// Finds the messages whose annotations match a full-text search query.
+    pub fn search(&self, query: &str) -> Result<Vec<MessageKey>, Error> {
+        let db = self.0.get()?;
+
+        let messages = db
+            .prepare(
+                r#"
+                SELECT DISTINCT a.channel_name, a.sent_at, a.username
+                FROM annotations_fts
+                JOIN annotations AS a ON a.annotation_id = annotations_fts.rowid
+                WHERE annotations_fts MATCH ?1
+                LIMIT ?2
+                "#,
+            )?
+            .query_map((query, MAX_SEARCH_RESULTS), |row| {
+                Ok(MessageKey { channel: row.get(0)?, sent_at: row.get(1)?, username: row.get(2)? })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(messages)
+    }
+
+    pub fn create(
+        &self,
+        message: &MessageKey,
+        author: &str,
+        content: &str,
+    ) -> Result<Annotation, Error> {
+        let content = check_content(content)?;
+
+        let annotation = self.0.get()?.query_row(
+            &format!(
+                r#"
+                INSERT INTO annotations
+                    (channel_name, sent_at, username, author, content, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                RETURNING {COLUMNS}
+                "#
+            ),
+            (&message.channel, message.sent_at, &message.username, author, content, Utc::now()),
+            Annotation::from_row,
+        )?;
+
+        Ok(annotation)
+    }
+
+    /// Replaces the content of an annotation, which keeps its author.
+    pub fn update(&self, id: u64, content: &str) -> Result<Option<Annotation>, Error> {
+        let content = check_content(content)?;
+
+        let annotation = self
+            .0
+            .get()?
+            .query_row(
+                &format!(
+                    r#"
+                    UPDATE annotations SET content = ?2, updated_at = ?3
+                    WHERE annotation_id = ?1
+                    RETURNING {COLUMNS}
+                    "#
+                ),
+                (id, content, Utc::now()),
+                Annotation::from_row,
+            )
+            .optional()?;
+
+        Ok(annotation)
+    }
+
+    pub fn delete(&self, id: u64) -> Result<(), Error> {
+        self.0.get()?.execute("DELETE FROM annotations WHERE annotation_id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations() -> Annotations {
+        Annotations(side_db::memory(SCHEMA))
+    }
+
+    // A message sent `minute` minutes into 2020.
+    fn key(minute: i64) -> MessageKey {
+        MessageKey {
+            channel: "routes".to_string(),
+            sent_at: DateTime::from_timestamp(1_577_836_800 + minute * 60, 0).unwrap(),
+            username: "Shy Lynx".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_annotations() {
+        let annotations = annotations();
+
+        let ruling = annotations.create(&key(3), "alice", " Ruled legal in 2019. ").unwrap();
+        assert_eq!(ruling.content, "Ruled legal in 2019.");
+        annotations.create(&key(3), "bob", "Verified record").unwrap();
+        annotations.create(&key(5), "bob", "Obsolete route").unwrap();
+        assert!(annotations.create(&key(5), "bob", " ").is_err());
+
+        let rowids = HashMap::from([(key(3), 3), (key(4), 4)]);
+        let by_message = MessageAnnotations::new(
+            annotations.for_messages(&[key(3), key(4)]).unwrap(),
+            &rowids,
+            false,
+        );
+        assert_eq!(by_message.of(&3).iter().map(|a| a.author.as_str()).collect::<Vec<_>>(), [
+            "alice", "bob"
+        ]);
+        assert!(by_message.of(&4).is_empty());
+
+        assert_eq!(annotations.search("\"route\"").unwrap(), [key(5)]);
+        assert_eq!(annotations.search("\"ruled\" AND \"legal\"").unwrap(), [key(3)]);
+
+        // Edits are searched instead of the previous content.
+        let edited = annotations.update(ruling.id, "Ruled illegal in 2020.").unwrap().unwrap();
+        assert_eq!(edited.author, "alice");
+        assert_eq!(edited.message, key(3));
+        assert!(edited.updated_at.is_some());
+        assert_eq!(annotations.search("\"illegal\"").unwrap(), [key(3)]);
+        assert!(annotations.search("\"legal\"").unwrap().is_empty());
+
+        annotations.delete(ruling.id).unwrap();
+        assert!(annotations.get(ruling.id).unwrap().is_none());
+        assert!(annotations.search("\"illegal\"").unwrap().is_empty());
+        assert!(annotations.update(ruling.id, "Gone").unwrap().is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::Message;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("`secret` must be set to anonymise users")]
+    MissingSecret,
+}
+
+/// The `[import.anonymise]` section of the configuration.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnonymiseConfig {
+    /// Replace usernames and avatars with pseudonyms and identicons.
+    pub enabled: bool,
+    /// Secret the pseudonyms are derived from. Without it, anyone could find
+    /// the pseudonym of a user by hashing their name.
+    pub secret: Option<String>,
+    /// Users who agreed to appear under their own name and avatar.
+    pub named_users: Vec<String>,
+}
+
+const ADJECTIVES: &[&str] = &[
+    "Agile", "Bold", "Brave", "Bright", "Calm", "Clever", "Curious", "Daring", "Eager", "Fancy",
+    "Fierce", "Gentle", "Giddy", "Grumpy", "Happy", "Hasty", "Humble", "Jolly", "Keen", "Lucky",
+    "Mellow", "Mighty", "Nimble", "Noble", "Proud", "Quiet", "Rapid", "Shy", "Sleepy", "Swift",
+    "Witty", "Zesty",
+];
+
+const ANIMALS: &[&str] = &[
+    "Badger", "Beaver", "Bison", "Camel", "Crane", "Crow", "Dingo", "Falcon", "Ferret", "Gecko",
+    "Heron", "Ibex", "Jackal", "Koala", "Lemur", "Lynx", "Marten", "Moose", "Newt", "Ocelot",
+    "Otter", "Panda", "Puffin", "Quokka", "Raven", "Salmon", "Stoat", "Tapir", "Toucan", "Walrus",
+    "Wombat", "Yak",
+];
+
+/// A stable name and avatar standing in for a user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pseudonym {
+    pub username: String,
+    pub avatar: String,
+}
+
+/// Replaces users with pseudonyms derived from their name and a secret.
+pub struct Anonymiser {
+    secret: String,
+    named_users: HashSet<String>,
+    pseudonyms: HashMap<String, Pseudonym>,
+}
+
+impl Anonymiser {
+    /// Returns `None` if anonymisation isn't enabled.
+    pub fn new(config: &AnonymiseConfig) -> Result<Option<Self>, Error> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            secret: config.secret.clone().ok_or(Error::MissingSecret)?,
+            named_users: config.named_users.iter().cloned().collect(),
+            pseudonyms: HashMap::new(),
+        }))
+    }
+
+    /// The pseudonym of a user, or `None` if they are named.
+    pub fn pseudonym(&mut self, username: &str) -> Option<&Pseudonym> {
+        if self.named_users.contains(username) {
+            return None;
+        }
+
+        if !self.pseudonyms.contains_key(username) {
+            let pseudonym = derive_pseudonym(&self.secret, username);
+            self.pseudonyms.insert(username.to_string(), pseudonym);
+        }
+
+        self.pseudonyms.get(username)
+    }
+
+    /// The name a user appears under in the archive.
+    pub fn username(&mut self, username: &str) -> String {
+        self.pseudonym(username).map_or_else(|| username.to_string(), |p| p.username.clone())
+    }
+
+    pub fn anonymise(&mut self, message: &mut Message) {
+        if let Some(Pseudonym { username, avatar }) = self.pseudonym(&message.username) {
+            message.username.clone_from(username);
+            message.avatar.clone_from(avatar);
+        }
+    }
+}
+
+fn derive_pseudonym(secret: &str, username: &str) -> Pseudonym {
+    let digest = Sha256::new()
+        .chain_update(secret.as_bytes())
+        .chain_update([0])
+        .chain_update(username.as_bytes())
+        .finalize();
+
+    let adjective = ADJECTIVES[digest[0] as usize % ADJECTIVES.len()];
+    let animal = ANIMALS[digest[1] as usize % ANIMALS.len()];
+    let discriminator = u16::from_be_bytes([digest[2], digest[3]]) % 10000;
+    let key = digest[4..12].iter().fold(String::new(), |mut key, byte| {
+        let _ = write!(key, "{byte:02x}");
+        key
+    });
+
+    Pseudonym {
+        username: format!("{adjective} {animal} #{discriminator:04}"),
+        avatar: format!("/identicon/{key}.svg"),
+    }
+}
+
+/// Renders the identicon of a pseudonym as SVG: a 5×5 horizontally symmetric
+/// pattern, colored after its key.
+///
+/// Returns `None` if the key isn't a hexadecimal string, as found in the
+/// avatars of pseudonyms.
+pub fn identicon(key: &str) -> Option<String> {
+    if key.is_empty() || key.len() > 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digest = Sha256::digest(key.as_bytes());
+    let hue = u16::from_be_bytes([digest[0], digest[1]]) % 360;
+
+    let mut path = String::new();
+    for row in 0..5 {
+        for column in 0..3 {
+            let bit = row * 3 + column;
+            if digest[2 + bit / 8] & (1 << (bit % 8)) == 0 {
+                continue;
+            }
+
+            let _ = write!(path, "M{column} {row}h1v1h-1z");
+            if column < 2 {
+                let _ = write!(path, "M{} {row}h1v1h-1z", 4 - column);
+            }
+        }
+    }
+
+    Some(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-0.5 -0.5 6 6" shape-rendering="crispEdges"><rect x="-0.5" y="-0.5" width="6" height="6" fill="#f0f0f0"/><path fill="hsl({hue} 55% 50%)" d="{path}"/></svg>"##
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonyms() {
+        let config = AnonymiseConfig {
+            enabled: true,
+            secret: Some("secret".to_string()),
+            named_users: vec!["alice".to_string()],
+        };
+        let mut anonymiser = Anonymiser::new(&config).unwrap().unwrap();
+
+        assert_eq!(anonymiser.username("alice"), "alice");
+
+        let bob = anonymiser.pseudonym("bob").cloned().unwrap();
+        assert_ne!(bob.username, "bob");
+        assert!(bob.avatar.starts_with("/identicon/"));
+        // Pseudonyms are stable, but depend on the secret.
+        assert_eq!(derive_pseudonym("secret", "bob"), bob);
+        assert_ne!(derive_pseudonym("other", "bob"), bob);
+        assert_ne!(anonymiser.pseudonym("carol"), Some(&bob));
+
+        let disabled = AnonymiseConfig { enabled: false, ..config.clone() };
+        assert!(Anonymiser::new(&disabled).unwrap().is_none());
+        let without_secret = AnonymiseConfig { secret: None, ..config };
+        assert!(Anonymiser::new(&without_secret).is_err());
+    }
+
+    #[test]
+    fn test_identicon() {
+        let key = derive_pseudonym("secret", "bob").avatar;
+        let key = key.strip_prefix("/identicon/").unwrap().strip_suffix(".svg").unwrap();
+
+        let svg = identicon(key).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(identicon(key), Some(svg));
+        assert!(identicon("../etc/passwd").is_none());
+    }
+}
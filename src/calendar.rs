@@ -0,0 +1,113 @@
+use chrono::{Datelike, NaiveDate};
+use serde::Deserialize;
+
+/// Which year of a channel the calendar shows.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CalendarQuery {
+    /// Defaults to the latest year with messages.
+    pub year: Option<i32>,
+}
+
+/// A day with messages in a channel, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub messages: u64,
+    /// The first message sent that day.
+    pub first_rowid: u64,
+}
+
+/// A month of the calendar, laid out in weeks starting on Monday.
+pub struct CalendarMonth {
+    /// First day of the month.
+    pub start: NaiveDate,
+    pub messages: u64,
+    /// Days of the month, `None` padding the first and last weeks.
+    pub weeks: Vec<[Option<CalendarDay>; 7]>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalendarDay {
+    pub day: u32,
+    /// The number of messages and first message of the day, if it has any.
+    pub messages: Option<Day>,
+}
+
+/// Lays out the months of a year with messages, given the days of that year
+/// with messages in chronological order.
+pub fn months(days: &[Day]) -> Vec<CalendarMonth> {
+    let mut months: Vec<CalendarMonth> = Vec::new();
+
+    for day in days {
+        let Some(start) = day.date.with_day(1) else {
+            continue;
+        };
+
+        if months.last().is_none_or(|month| month.start != start) {
+            months.push(CalendarMonth { start, messages: 0, weeks: weeks(start) });
+        }
+
+        let month = months.last_mut().expect("month was just added");
+        month.messages += day.messages;
+
+        // The first week is padded with the days of the previous month.
+        let index = (start.weekday().num_days_from_monday() + day.date.day0()) as usize;
+        if let Some(calendar_day) = month.weeks[index / 7][index % 7].as_mut() {
+            calendar_day.messages = Some(*day);
+        }
+    }
+
+    months
+}
+
+// The days of the month starting on `start`, without messages.
+fn weeks(start: NaiveDate) -> Vec<[Option<CalendarDay>; 7]> {
+    let mut weeks = vec![[None; 7]];
+    let mut weekday = start.weekday().num_days_from_monday() as usize;
+
+    for date in start.iter_days().take_while(|date| date.month() == start.month()) {
+        if weekday == 7 {
+            weeks.push([None; 7]);
+            weekday = 0;
+        }
+
+        let week = weeks.last_mut().expect("weeks aren't empty");
+        week[weekday] = Some(CalendarDay { day: date.day(), messages: None });
+        weekday += 1;
+    }
+
+    weeks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(date: &str, messages: u64, first_rowid: u64) -> Day {
+        Day { date: date.parse().unwrap(), messages, first_rowid }
+    }
+
+    #[test]
+    fn test_months() {
+        let days = [day("2019-09-01", 3, 10), day("2019-09-30", 1, 20), day("2019-12-25", 2, 30)];
+        let months = months(&days);
+
+        assert_eq!(months.len(), 2);
+        assert_eq!(months[0].start.to_string(), "2019-09-01");
+        assert_eq!(months[0].messages, 4);
+        assert_eq!(months[1].messages, 2);
+
+        // 2019-09-01 was a Sunday, and 2019-09-30 a Monday.
+        let september = &months[0].weeks;
+        assert_eq!(september.len(), 6);
+        assert!(september[0][..6].iter().all(Option::is_none));
+        assert_eq!(september[0][6], Some(CalendarDay { day: 1, messages: Some(days[0]) }));
+        assert_eq!(september[1][0], Some(CalendarDay { day: 2, messages: None }));
+        assert_eq!(september[5][0], Some(CalendarDay { day: 30, messages: Some(days[1]) }));
+        assert!(september[5][1..].iter().all(Option::is_none));
+
+        let december = &months[1].weeks;
+        assert_eq!(december[4][2], Some(CalendarDay { day: 25, messages: Some(days[2]) }));
+    }
+}
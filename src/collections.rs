@@ -0,0 +1,360 @@
+use std::path::{Path, PathBuf};
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use password_hash::rand_core::{OsRng, RngCore};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+
+pub use crate::side_db::Error;
+use crate::{side_db, Message, MessageKey, COLLECTIONS_PATH};
+
+/// Number of messages shown before and after each message of a collection.
+pub const CONTEXT_SIZE: usize = 2;
+
+/// Maximum length of the name of a collection, in characters.
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// Maximum length of the note of an entry, in characters.
+pub const MAX_NOTE_LENGTH: usize = 2000;
+
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS collections (
+        collection_id TEXT PRIMARY KEY,
+        owner TEXT NOT NULL,
+        name TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS collections_owners
+    ON collections(owner);
+
+    CREATE TABLE IF NOT EXISTS collection_entries (
+        entry_id INTEGER PRIMARY KEY,
+        collection_id TEXT NOT NULL
+            REFERENCES collections(collection_id) ON DELETE CASCADE,
+        channel_name TEXT NOT NULL,
+        sent_at TEXT NOT NULL,
+        username TEXT NOT NULL,
+        note TEXT,
+        added_at TEXT NOT NULL,
+        UNIQUE (collection_id, channel_name, sent_at, username)
+    );
+"#;
+
+/// The `[collections]` section of the configuration.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionsConfig {
+    /// Whether users can keep collections. Disabled by default, so that
+    /// serving doesn't write next to the archive.
+    pub enabled: bool,
+    /// Database holding the collections. Unlike the archive, it's written to
+    /// while serving, so it must be on a writable filesystem.
+    pub path: PathBuf,
+}
+
+impl Default for CollectionsConfig {
+    fn default() -> Self {
+        Self { enabled: false, path: PathBuf::from(COLLECTIONS_PATH) }
+    }
+}
+
+/// A collection, as listed on the page of its owner.
+#[derive(Serialize, Debug)]
+pub struct CollectionSummary {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Number of messages in the collection.
+    pub entries: u64,
+}
+
+/// A named list of messages, shared by its id.
+#[derive(Serialize, Debug)]
+pub struct Collection {
+    pub id: String,
+    pub owner: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Messages of the collection, in the order they were added.
+    pub entries: Vec<Entry>,
+    /// Ids of the entries whose message can't be shown, given to the owner
+    /// only so that they can remove them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hidden: Vec<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Entry {
+    /// The id of the entry, which unlike the id of its message doesn't depend
+    /// on the build of the archive.
+    pub id: u64,
+    /// The message, which stays in the collection when the archive is
+    /// rebuilt.
+    pub message: MessageKey,
+    pub note: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+/// A message of a collection along with the messages around it, for display.
+pub struct EntryContext {
+    pub entry: Entry,
+    /// The id of the message in this build of the archive, as in
+    /// `/message/<id>`.
+    pub message: u64,
+    pub channel_id: u64,
+    pub channel_name: String,
+    /// Messages around the entry in chronological order, including it.
+    pub messages: Vec<Message>,
+}
+
+/// Body of the requests creating a collection.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewCollection {
+    pub name: String,
+}
+
+/// Body of the requests adding a message to a collection.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewEntry {
+    /// The id of the message, as in `/message/<id>`.
+    pub message: u64,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// Checks the name of a collection, returning it without surrounding spaces.
+pub fn check_name(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Invalid("The name of a collection can't be empty.".to_string()));
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(Error::Invalid(format!(
+            "The name of a collection can't be longer than {MAX_NAME_LENGTH} characters."
+        )));
+    }
+
+    Ok(name)
+}
+
+/// Checks the note of an entry, leaving out empty notes.
+pub fn check_note(note: Option<&str>) -> Result<Option<&str>, Error> {
+    let note = note.map(str::trim).filter(|note| !note.is_empty());
+    if note.is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(Error::Invalid(format!(
+            "Notes can't be longer than {MAX_NOTE_LENGTH} characters."
+        )));
+    }
+
+    Ok(note)
+}
+
+/// The collections of messages kept by users, stored apart from the archive
+/// so that the archive stays read-only.
+pub struct Collections(Pool<SqliteConnectionManager>);
+
+impl Collections {
+    /// Opens the database of collections, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self(side_db::open(path, SCHEMA)?))
+    }
+
+    /// Lists the collections of a user, the latest first.
+    pub fn list(&self, owner: &str) -> Result<Vec<CollectionSummary>, Error> {
+        let db = self.0.get()?;
+
+        let collections = db
+            .prepare(
+                r#"
+                SELECT c.collection_id, c.name, c.created_at, COUNT(e.collection_id)
+                FROM collections AS c
+                LEFT JOIN collection_entries AS e ON e.collection_id = c.collection_id
+                WHERE c.owner = ?1
+                GROUP BY c.collection_id
+                ORDER BY c.created_at DESC, c.rowid DESC
+                "#,
+            )?
+            .query_map([owner], |row| {
+                Ok(CollectionSummary {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    created_at: row.get(2)?,
+                    entries: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(collections)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Collection>, Error> {
+        let db = self.0.get()?;
+
+        let Some((owner, name, created_at)) = db
+            .query_row(
+                "SELECT owner, name, created_at FROM collections WHERE collection_id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let entries = db
+            .prepare(
+                r#"
+                SELECT entry_id, channel_name, sent_at, username, note, added_at
+                FROM collection_entries
+                WHERE collection_id = ?1
+                ORDER BY added_at, entry_id
+                "#,
+            )?
+            .query_map([id], |row| {
+                let message = MessageKey {
+                    channel: row.get(1)?,
+                    sent_at: row.get(2)?,
+                    username: row.get(3)?,
+                };
+                Ok(Entry { id: row.get(0)?, message, note: row.get(4)?, added_at: row.get(5)? })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let id = id.to_string();
+        Ok(Some(Collection { id, owner, name, created_at, entries, hidden: Vec::new() }))
+    }
+
+    /// Creates an empty collection with a random id.
+    pub fn create(&self, owner: &str, name: &str) -> Result<CollectionSummary, Error> {
+        let db = self.0.get()?;
+
+        let mut bytes = [0; 9];
+        OsRng.fill_bytes(&mut bytes);
+        let id = BASE64_URL_SAFE_NO_PAD.encode(bytes);
+        let name = check_name(name)?.to_string();
+        let created_at = Utc::now();
+
+        db.execute(
+            r#"
+            INSERT INTO collections (collection_id, owner, name, created_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+            (&id, owner, &name, created_at),
+        )?;
+
+        Ok(CollectionSummary { id, name, created_at, entries: 0 })
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), Error> {
+        self.0.get()?.execute("DELETE FROM collections WHERE collection_id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Adds a message to a collection, or updates its note if it's already
+    /// there.
+    pub fn add_entry(
+        &self,
+        id: &str,
+        message: MessageKey,
+        note: Option<&str>,
+    ) -> Result<Entry, Error> {
+        let db = self.0.get()?;
+
+        let note = check_note(note)?.map(str::to_string);
+        let (entry_id, added_at) = db.query_row(
+            r#"
+            INSERT INTO collection_entries
+                (collection_id, channel_name, sent_at, username, note, added_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (collection_id, channel_name, sent_at, username)
+            DO UPDATE SET note = excluded.note
+            RETURNING entry_id, added_at
+            "#,
+            (id, &message.channel, message.sent_at, &message.username, &note, Utc::now()),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(Entry { id: entry_id, message, note, added_at })
+    }
+
+    /// Removes an entry by its id, which works even if its message is no
+    /// longer in the archive.
+    pub fn remove_entry(&self, id: &str, entry: u64) -> Result<(), Error> {
+        self.0.get()?.execute(
+            "DELETE FROM collection_entries WHERE collection_id = ?1 AND entry_id = ?2",
+            (id, entry),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collections() -> Collections {
+        Collections(side_db::memory(SCHEMA))
+    }
+
+    // A message sent `minute` minutes into 2020.
+    fn key(minute: i64) -> MessageKey {
+        MessageKey {
+            channel: "routes".to_string(),
+            sent_at: DateTime::from_timestamp(1_577_836_800 + minute * 60, 0).unwrap(),
+            username: "Shy Lynx".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_collections() {
+        let collections = collections();
+
+        let routes = collections.create("alice", "  Routes ").unwrap();
+        assert_eq!(routes.name, "Routes");
+        assert_eq!(routes.id.len(), 12);
+        collections.create("bob", "Memes").unwrap();
+
+        let first = collections.add_entry(&routes.id, key(3), Some("First route")).unwrap();
+        collections.add_entry(&routes.id, key(1), Some(" ")).unwrap();
+        // Adding a message again updates its note, keeping its entry.
+        let fastest = collections.add_entry(&routes.id, key(3), Some("Fastest route")).unwrap();
+        assert_eq!(fastest.id, first.id);
+
+        let collection = collections.get(&routes.id).unwrap().unwrap();
+        assert_eq!(collection.owner, "alice");
+        assert_eq!(
+            collection.entries.iter().map(|e| (&e.message, e.note.as_deref())).collect::<Vec<_>>(),
+            [(&key(3), Some("Fastest route")), (&key(1), None)]
+        );
+
+        let list = collections.list("alice").unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].entries, 2);
+
+        // Entries are only removed from their own collection.
+        let memes = collections.create("bob", "More memes").unwrap();
+        collections.remove_entry(&memes.id, first.id).unwrap();
+        collections.remove_entry(&routes.id, first.id).unwrap();
+        let collection = collections.get(&routes.id).unwrap().unwrap();
+        assert_eq!(collection.entries.iter().map(|e| &e.message).collect::<Vec<_>>(), [&key(1)]);
+
+        collections.delete(&routes.id).unwrap();
+        assert!(collections.get(&routes.id).unwrap().is_none());
+        assert!(collections.list("alice").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check_name(" ").is_err());
+        assert!(check_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert_eq!(check_note(Some("  note ")).unwrap(), Some("note"));
+        assert_eq!(check_note(Some("")).unwrap(), None);
+        assert!(check_note(Some(&"a".repeat(MAX_NOTE_LENGTH + 1))).is_err());
+    }
+}
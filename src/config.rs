@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::access::{AccessRule, AuthConfig};
+use crate::annotations::AnnotationsConfig;
+use crate::collections::CollectionsConfig;
+use crate::db::ImportConfig;
+use crate::index::IndexConfig;
+use crate::telemetry::MetricsConfig;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("reading {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("parsing {0:?}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+/// Contents of the configuration file passed with `--config`.
+///
+/// Every section is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Which channels of the backup are imported.
+    pub import: ImportConfig,
+    /// How visitors authenticate.
+    pub auth: AuthConfig,
+    /// Channels restricted to some users.
+    pub access: Vec<AccessRule>,
+    /// Where the collections of messages are kept.
+    pub collections: CollectionsConfig,
+    /// Where the annotations of messages are kept, and who writes them.
+    pub annotations: AnnotationsConfig,
+    /// What the index page shows.
+    pub index: IndexConfig,
+    /// Who can read the metrics.
+    pub metrics: MetricsConfig,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| Error::Parse(path.to_path_buf(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [auth]
+            proxy_header = "X-Forwarded-User"
+
+            [[auth.users]]
+            name = "alice"
+            password_hash = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaA"
+
+            [auth.groups]
+            mods = ["alice"]
+
+            [[access]]
+            category = "Moderation"
+            allow = ["@mods"]
+
+            [import]
+            exclude_channels = ["bot-spam"]
+
+            [collections]
+            enabled = true
+            path = "/var/lib/amardiscord/collections.sqlite"
+
+            [annotations]
+            editors = ["@mods"]
+
+            [index]
+            name = "SoulsSpeedruns"
+            default_channel = "announcements"
+            base_url = "https://archive.example.com"
+
+            [metrics]
+            allow = ["prometheus"]
+            "#,
+        )
+        .expect("Couldn't parse config");
+
+        assert_eq!(config.auth.proxy_header.as_deref(), Some("X-Forwarded-User"));
+        assert_eq!(config.auth.users[0].name, "alice");
+        assert_eq!(config.auth.groups["mods"], ["alice"]);
+        assert_eq!(config.access[0].category.as_deref(), Some("Moderation"));
+        assert_eq!(config.import.exclude_channels, ["bot-spam"]);
+        assert!(config.collections.enabled);
+        assert_eq!(config.collections.path, Path::new("/var/lib/amardiscord/collections.sqlite"));
+        assert_eq!(config.annotations.editors, ["@mods"]);
+        assert_eq!(config.index.default_channel.as_deref(), Some("announcements"));
+        assert_eq!(config.index.base_url.as_deref(), Some("https://archive.example.com"));
+        assert_eq!(config.metrics.allow, ["prometheus"]);
+        assert!(toml::from_str::<Config>("").is_ok());
+    }
+}
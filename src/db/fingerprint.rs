@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use sha2::{Digest, Sha256};
+
+use crate::db::{Error, ImportConfig};
+
+/// Computes a fingerprint of backup directories and of the configuration they
+/// are imported with.
+///
+/// The fingerprint is a SHA-256 digest of the import configuration and of the
+/// relative path, size and modification time of every file in the directories,
+/// so that any change to the backups or to the channels imported from them
+/// results in a different fingerprint.
+pub(crate) fn fingerprint(paths: &[PathBuf], config: &ImportConfig) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_string(config)?);
+
+    for path in paths {
+        // Backups are merged in the order of their names, so the names matter
+        // as soon as there are several. The fingerprint of a single backup is
+        // left as it was before backups could be merged.
+        if paths.len() > 1 {
+            hasher.update(format!("{}\n", path.file_name().unwrap_or_default().to_string_lossy()));
+        }
+
+        hash_files(&mut hasher, path)?;
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn hash_files(hasher: &mut Sha256, path: &Path) -> Result<(), Error> {
+    let mut files = Vec::new();
+    list_files(path, &mut files)?;
+    files.sort();
+
+    for file in files {
+        let metadata = file.metadata()?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos());
+        let relative_path = file.strip_prefix(path).unwrap_or(&file);
+
+        hasher.update(format!("{}\t{}\t{mtime}\n", relative_path.display(), metadata.len()));
+    }
+
+    Ok(())
+}
+
+fn list_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! Exports of [DiscordChatExporter](https://github.com/Tyrrrz/DiscordChatExporter)
+//! in JSON, which hold a single channel per file.
+//!
+//! Exports hold no permissions, so channels are considered visible to
+//! `@everyone`. The channels of a category are spread across files, which are
+//! grouped by the ids of their categories and channels, in the order Discord
+//! created them.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::db::import::{
+    list_json_files, Emitter, Importer, Record, Sink, OTHER_CHANNELS, OTHER_CHANNELS_CATEGORY,
+};
+use crate::db::visibility::Facts;
+use crate::db::Error;
+use crate::{Message, MessageContent};
+
+pub(crate) struct ChatExporter;
+
+/// An exported channel, along with where it belongs in the archive.
+///
+/// Channels exported in several parts (e.g. by date range) are imported as a
+/// single channel.
+pub(crate) struct ChannelFile {
+    path: PathBuf,
+    category: u64,
+    category_name: String,
+    channel: u64,
+}
+
+impl AsRef<Path> for ChannelFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChannelHeader {
+    id: String,
+    #[serde(rename = "type")]
+    channel_type: String,
+    category_id: Option<String>,
+    category: Option<String>,
+    name: String,
+}
+
+impl ChannelHeader {
+    // Category ids and names are missing from the exports of channels that
+    // aren't in any category.
+    fn category(&self) -> Option<(&str, &str)> {
+        self.category_id.as_deref().zip(self.category.as_deref())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedMessage {
+    timestamp: DateTime<Utc>,
+    content: String,
+    author: Author,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Author {
+    name: String,
+    #[serde(default)]
+    avatar_url: String,
+}
+
+#[derive(Deserialize)]
+struct Attachment {
+    url: String,
+}
+
+impl ExportedMessage {
+    // Attachments are linked after the text of the message. Messages without
+    // either, such as join notifications, are left out.
+    fn into_message(self) -> Option<Message> {
+        let text = std::iter::once(self.content)
+            .chain(self.attachments.into_iter().map(|attachment| attachment.url))
+            .filter(|part| !part.is_empty())
+            .join("\n");
+
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(Message {
+            content: MessageContent::from_text(&text),
+            username: self.author.name,
+            avatar: self.author.avatar_url,
+            sent_at: self.timestamp,
+            rowid: 0,
+            redacted: false,
+        })
+    }
+}
+
+// Discord's number for a channel type, as named by DiscordChatExporter.
+// Threads are imported as text channels, and unknown types are assumed to hold
+// text.
+fn channel_type(name: &str) -> u64 {
+    match name {
+        "GuildVoiceChat" => 2,
+        "GuildCategory" => 4,
+        "GuildNews" | "GuildAnnouncement" => 5,
+        "GuildStageVoice" => 13,
+        "GuildDirectory" => 14,
+        "GuildForum" => 15,
+        _ => 0,
+    }
+}
+
+// Parses the id of a channel or category. These snowflake ids grow over time.
+fn snowflake(id: &str) -> Option<u64> {
+    id.parse().ok()
+}
+
+// Reads the `channel` object of an export, which comes before its messages.
+fn read_header(path: &Path) -> Result<ChannelHeader, Error> {
+    struct HeaderReader<'a> {
+        header: &'a mut Option<ChannelHeader>,
+    }
+
+    impl<'de> Visitor<'de> for HeaderReader<'_> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a DiscordChatExporter export")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+            while let Some(key) = map.next_key::<String>()? {
+                if key == "channel" {
+                    *self.header = Some(map.next_value()?);
+                    // Stop before the messages.
+                    return Err(de::Error::custom("header read"));
+                }
+                map.next_value::<IgnoredAny>()?;
+            }
+
+            Ok(())
+        }
+    }
+
+    let reader = File::open(path).map_err(|e| Error::LoadChannel(path.to_path_buf(), e))?;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let mut header = None;
+
+    let result = deserializer.deserialize_map(HeaderReader { header: &mut header });
+    header.ok_or_else(|| match result {
+        Err(e) if !e.is_data() => e.into(),
+        _ => Error::Generic(format!("{path:?} isn't a DiscordChatExporter export.")),
+    })
+}
+
+impl Importer for ChatExporter {
+    type File = ChannelFile;
+
+    /// Lists the exported channels, emitting a [`Record::Category`] for each of
+    /// their categories.
+    fn list_files(&self, path: &Path, sink: &mut impl Sink) -> Result<Vec<ChannelFile>, Error> {
+        let mut exports = list_json_files(path)?
+            .into_iter()
+            .map(|path| read_header(&path).map(|header| (path, header)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Channels without a category come last.
+        exports.sort_by_key(|(path, header)| {
+            let category = header.category().and_then(|(id, _)| snowflake(id));
+            let channel = snowflake(&header.id);
+            (category.unwrap_or(u64::MAX), channel.unwrap_or(u64::MAX), path.clone())
+        });
+
+        let mut categories = HashMap::new();
+        let mut channels = HashMap::new();
+        let mut files = Vec::new();
+
+        for (path, header) in exports {
+            let (category_id, category_name) = header.category().unwrap_or(("", OTHER_CHANNELS));
+            let category_name = category_name.to_string();
+
+            let next_category = categories.len() as u64 + 1;
+            let category = match categories.entry(category_id.to_string()) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let category = if category_id.is_empty() {
+                        OTHER_CHANNELS_CATEGORY
+                    } else {
+                        next_category
+                    };
+                    sink.record(Record::Category {
+                        category,
+                        id: header.category().and_then(|(id, _)| snowflake(id)),
+                        name: category_name.clone(),
+                        public: true,
+                    })?;
+                    *entry.insert(category)
+                },
+            };
+
+            let category_channels = channels.entry(category).or_insert_with(HashMap::new);
+            let next_channel = category_channels.len() as u64;
+            let channel = *category_channels.entry(header.id).or_insert(next_channel);
+
+            files.push(ChannelFile { path, category, category_name, channel });
+        }
+
+        Ok(files)
+    }
+
+    fn read_file<'de, D: Deserializer<'de>, S: Sink>(
+        &self,
+        file: &ChannelFile,
+        deserializer: D,
+        emitter: &mut Emitter<S>,
+    ) -> Result<(), D::Error> {
+        deserializer.deserialize_map(ExportVisitor { file, emitter })
+    }
+}
+
+struct ExportVisitor<'a, 'b, S> {
+    file: &'a ChannelFile,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> Visitor<'de> for ExportVisitor<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a DiscordChatExporter export")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Self { file, emitter } = self;
+        let category_facts = Facts { name: Some(file.category_name.clone()), public: Some(true) };
+        let mut header: Option<ChannelHeader> = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "channel" => header = Some(map.next_value()?),
+                // Only visible text channels are imported, so don't bother
+                // reading the messages of other channels if that's already
+                // known.
+                "messages"
+                    if header.as_ref().is_none_or(|header| {
+                        let facts = Facts { name: Some(header.name.clone()), public: Some(true) };
+                        channel_type(&header.channel_type) == 0
+                            && !emitter.config.is_hidden(&category_facts, &facts)
+                    }) =>
+                {
+                    map.next_value_seed(MessagesSeed { file, emitter: &mut *emitter })?
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        let header = header.ok_or_else(|| de::Error::missing_field("channel"))?;
+
+        emitter.emit(Record::Channel {
+            category: file.category,
+            channel: file.channel,
+            id: snowflake(&header.id),
+            channel_type: channel_type(&header.channel_type),
+            name: header.name,
+            public: true,
+        })
+    }
+}
+
+struct MessagesSeed<'a, 'b, S> {
+    file: &'a ChannelFile,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for MessagesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for MessagesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of messages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(message) = seq.next_element::<ExportedMessage>()? {
+            if let Some(message) = message.into_message() {
+                self.emitter.emit(Record::Message {
+                    category: self.file.category,
+                    channel: self.file.channel,
+                    message,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,605 @@
+//! Backups made with [discord-backup](https://github.com/StenniHub/discord-backup),
+//! either as the single file written by the tool or split into one file per
+//! category.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+
+use crate::db::import::{
+    list_json_files, other_channels_facts, Emitter, Importer, Record, Sink, OTHER_CHANNELS,
+    OTHER_CHANNELS_CATEGORY,
+};
+use crate::db::visibility::{is_public, Facts, Permission};
+use crate::db::Error;
+use crate::Message;
+
+pub(crate) struct DiscordBackup;
+
+/// A backup file queued for import, along with the category it belongs to.
+pub(crate) enum BackupFile {
+    /// The single `.json` file written by the backup tool, holding every
+    /// category and channel of the server.
+    Export { path: PathBuf },
+    /// A `categories/<n>.json` file holding a category and its channels.
+    Category { path: PathBuf, category: u64 },
+    /// An `other_channels/<n>.json` file holding a single channel.
+    Channel { path: PathBuf, category: u64, channel: u64 },
+}
+
+impl AsRef<Path> for BackupFile {
+    fn as_ref(&self) -> &Path {
+        match self {
+            BackupFile::Export { path }
+            | BackupFile::Category { path, .. }
+            | BackupFile::Channel { path, .. } => path,
+        }
+    }
+}
+
+// Lists the `<number>.json` files in `path`, sorted by number.
+fn list_numbered_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut indices = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+
+        // Skip non-.json files.
+        if path.extension().and_then(|s| s.to_str()) != Some("json") {
+            continue;
+        }
+
+        // Extract stems from filenames (e.g. `1.json` -> `1`) and try parsing
+        // them into an integer.
+        if let Some(index) =
+            path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok())
+        {
+            indices.push(index);
+        }
+    }
+
+    // Category file names are ordered in the same way they are on a server.
+    // Sort them to replicate the server's structure.
+    indices.sort_unstable();
+
+    Ok(indices.into_iter().map(|i| path.join(format!("{i}.json"))).collect())
+}
+
+// Finds the export file at the top level of a backup directory.
+fn find_export_file(path: &Path) -> Result<PathBuf, Error> {
+    let files = list_json_files(path)?;
+
+    match <[_; 1]>::try_from(files) {
+        Ok([file]) => Ok(file),
+        Err(files) if files.is_empty() => Err(Error::Generic(format!(
+            "{path:?} contains neither a backup `.json` file nor a `categories` directory."
+        ))),
+        Err(files) => Err(Error::Generic(format!(
+            "{path:?} contains several `.json` files, expected a single backup: {files:?}"
+        ))),
+    }
+}
+
+impl Importer for DiscordBackup {
+    type File = BackupFile;
+
+    /// Lists the files of a backup directory in import order, emitting a
+    /// [`Record::Category`] for the "Other channels" category if needed.
+    ///
+    /// The backup is either the single file written by the backup tool, or a
+    /// backup split into `categories` and `other_channels` directories, in
+    /// which case the top-level file is ignored.
+    fn list_files(&self, path: &Path, sink: &mut impl Sink) -> Result<Vec<BackupFile>, Error> {
+        let categories_path = path.join("categories");
+
+        if !categories_path.exists() {
+            return Ok(vec![BackupFile::Export { path: find_export_file(path)? }]);
+        }
+
+        let mut files = list_numbered_files(&categories_path)?
+            .into_iter()
+            .zip(1..)
+            .map(|(path, category)| BackupFile::Category { path, category })
+            .collect::<Vec<_>>();
+
+        let channels_path = path.join("other_channels");
+        if channels_path.exists() {
+            let category = OTHER_CHANNELS_CATEGORY;
+            let channels = list_numbered_files(&channels_path)?;
+
+            if !channels.is_empty() {
+                sink.record(Record::Category {
+                    category,
+                    id: None,
+                    name: OTHER_CHANNELS.to_string(),
+                    public: true,
+                })?;
+            }
+
+            files.extend(
+                channels.into_iter().zip(0..).map(|(path, channel)| BackupFile::Channel {
+                    path,
+                    category,
+                    channel,
+                }),
+            );
+        }
+
+        Ok(files)
+    }
+
+    fn read_file<'de, D: Deserializer<'de>, S: Sink>(
+        &self,
+        file: &BackupFile,
+        deserializer: D,
+        emitter: &mut Emitter<S>,
+    ) -> Result<(), D::Error> {
+        match *file {
+            BackupFile::Export { .. } => ExportSeed { emitter }.deserialize(deserializer),
+            BackupFile::Category { category, .. } => {
+                CategorySeed { category, emitter }.deserialize(deserializer)
+            },
+            BackupFile::Channel { category, channel, .. } => {
+                ChannelSeed { category, channel, category_facts: &other_channels_facts(), emitter }
+                    .deserialize(deserializer)
+            },
+        }
+    }
+}
+
+struct ExportSeed<'a, 'b, S> {
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for ExportSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for ExportSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a server backup")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut channels = false;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "channels" => {
+                    map.next_value_seed(ServerChannelsSeed { emitter: &mut *self.emitter })?;
+                    channels = true;
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        if channels {
+            Ok(())
+        } else {
+            Err(de::Error::missing_field("channels"))
+        }
+    }
+}
+
+// The `channels` object of an export, holding categories and the channels that
+// aren't in any category.
+struct ServerChannelsSeed<'a, 'b, S> {
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for ServerChannelsSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for ServerChannelsSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("categories and channels")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "categories" => {
+                    map.next_value_seed(CategoriesSeed { emitter: &mut *self.emitter })?;
+                },
+                "others" => {
+                    let has_channels = map.next_value_seed(ChildrenSeed {
+                        category: OTHER_CHANNELS_CATEGORY,
+                        category_facts: &other_channels_facts(),
+                        emitter: &mut *self.emitter,
+                    })?;
+
+                    if has_channels {
+                        self.emitter.emit(Record::Category {
+                            category: OTHER_CHANNELS_CATEGORY,
+                            id: None,
+                            name: OTHER_CHANNELS.to_string(),
+                            public: true,
+                        })?;
+                    }
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Categories are numbered from 1, in the order of the backup.
+struct CategoriesSeed<'a, 'b, S> {
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for CategoriesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for CategoriesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of categories")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        for category in 1.. {
+            let seed = CategorySeed { category, emitter: &mut *self.emitter };
+            if seq.next_element_seed(seed)?.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct CategorySeed<'a, 'b, S> {
+    category: u64,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for CategorySeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for CategorySeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a category")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Self { category, emitter } = self;
+        let mut facts = Facts::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => facts.name = Some(map.next_value()?),
+                "permissions" => {
+                    facts.public = Some(is_public(&map.next_value::<Vec<Permission>>()?))
+                },
+                "children" => {
+                    map.next_value_seed(ChildrenSeed {
+                        category,
+                        category_facts: &facts,
+                        emitter: &mut *emitter,
+                    })?;
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        let name = facts.name.ok_or_else(|| de::Error::missing_field("name"))?;
+
+        // Channels without permission overwrites inherit the server's, which
+        // let `@everyone` in.
+        emitter.emit(Record::Category {
+            category,
+            id: None,
+            name,
+            public: facts.public.unwrap_or(true),
+        })
+    }
+}
+
+// A list of channels, returning whether there was any.
+struct ChildrenSeed<'a, 'b, S> {
+    category: u64,
+    category_facts: &'a Facts,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for ChildrenSeed<'_, '_, S> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for ChildrenSeed<'_, '_, S> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of channels")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<bool, A::Error> {
+        let mut channel = 0;
+
+        loop {
+            let seed = ChannelSeed {
+                category: self.category,
+                channel,
+                category_facts: self.category_facts,
+                emitter: &mut *self.emitter,
+            };
+            if seq.next_element_seed(seed)?.is_none() {
+                return Ok(channel > 0);
+            }
+
+            channel += 1;
+        }
+    }
+}
+
+struct ChannelSeed<'a, 'b, S> {
+    category: u64,
+    channel: u64,
+    // What has been read of the category before this channel.
+    category_facts: &'a Facts,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for ChannelSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for ChannelSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a channel")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Self { category, channel, category_facts, emitter } = self;
+        let mut channel_type = None;
+        let mut facts = Facts::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "type" => channel_type = Some(map.next_value::<u64>()?),
+                "name" => facts.name = Some(map.next_value::<String>()?),
+                "permissions" => {
+                    facts.public = Some(is_public(&map.next_value::<Vec<Permission>>()?))
+                },
+                // Only visible text channels are imported, so don't bother
+                // reading the messages of other channels if that's already
+                // known.
+                "messages"
+                    if channel_type.is_none_or(|t| t == 0)
+                        && !emitter.config.is_hidden(category_facts, &facts) =>
+                {
+                    map.next_value_seed(MessagesSeed { category, channel, emitter: &mut *emitter })?
+                },
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        let channel_type = channel_type.ok_or_else(|| de::Error::missing_field("type"))?;
+        let name = facts.name.ok_or_else(|| de::Error::missing_field("name"))?;
+        let public = facts.public.unwrap_or(true);
+
+        emitter.emit(Record::Channel { category, channel, id: None, channel_type, name, public })
+    }
+}
+
+struct MessagesSeed<'a, 'b, S> {
+    category: u64,
+    channel: u64,
+    emitter: &'a mut Emitter<'b, S>,
+}
+
+impl<'de, S: Sink> DeserializeSeed<'de> for MessagesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+impl<'de, S: Sink> Visitor<'de> for MessagesSeed<'_, '_, S> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of messages")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(message) = seq.next_element::<Message>()? {
+            self.emitter.emit(Record::Message {
+                category: self.category,
+                channel: self.channel,
+                message,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::import::tests::{summarize, Records};
+    use crate::db::ImportConfig;
+
+    // Parses a category, or a whole export if `export` is set, summarizing the
+    // records.
+    fn parse(json: &str, export: bool) -> Vec<String> {
+        let mut records = Records::default();
+        let config = ImportConfig::default();
+        let mut emitter = Emitter { sink: &mut records, config: &config, error: None, messages: 0 };
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+
+        if export {
+            ExportSeed { emitter: &mut emitter }.deserialize(&mut deserializer)
+        } else {
+            CategorySeed { category: 1, emitter: &mut emitter }.deserialize(&mut deserializer)
+        }
+        .expect("Couldn't deserialize backup");
+
+        summarize(&records.0)
+    }
+
+    #[test]
+    fn test_stream_category() {
+        let summary = parse(
+            r#"{
+                "children": [
+                    {
+                        "messages": [
+                            { "content": "hi", "username": "a", "avatar": "", "sentAt": "2020-01-01T00:00:00Z" },
+                            { "content": "yo", "username": "b", "avatar": "", "sentAt": "2020-01-02T00:00:00Z" }
+                        ],
+                        "type": 0,
+                        "name": "general"
+                    },
+                    { "type": 2, "name": "voice", "messages": [{ "unparsed": true }] },
+                    { "type": 0, "name": "empty", "messages": null }
+                ],
+                "name": "Text channels"
+            }"#,
+            false,
+        );
+
+        assert_eq!(summary, [
+            "message 1 0 a hi",
+            "message 1 0 b yo",
+            "channel 0 general 0 true",
+            "channel 1 voice 2 true",
+            "channel 2 empty 0 true",
+            "category 1 Text channels true",
+        ]);
+    }
+
+    #[test]
+    fn test_stream_export() {
+        let summary = parse(
+            r#"{
+                "name": "Server",
+                "channels": {
+                    "categories": [
+                        {
+                            "name": "Text channels",
+                            "permissions": [],
+                            "children": [
+                                {
+                                    "type": 0,
+                                    "name": "general",
+                                    "permissions": [{ "roleName": "@everyone", "allow": "1024", "deny": "0" }],
+                                    "messages": [
+                                        { "content": "hi", "username": "a", "avatar": "", "sentAt": "2020-01-01T00:00:00Z" }
+                                    ]
+                                },
+                                {
+                                    "type": 0,
+                                    "name": "staff",
+                                    "permissions": [{ "roleName": "@everyone", "allow": "0", "deny": "1024" }],
+                                    "messages": [{ "unparsed": true }]
+                                }
+                            ]
+                        },
+                        {
+                            "children": [
+                                {
+                                    "type": 0,
+                                    "name": "mod-chat",
+                                    "messages": [
+                                        { "content": "psst", "username": "m", "avatar": "", "sentAt": "2020-01-01T00:00:00Z" }
+                                    ]
+                                }
+                            ],
+                            "permissions": [{ "roleName": "@everyone", "allow": "0", "deny": "1024" }],
+                            "name": "Moderation"
+                        }
+                    ],
+                    "others": [
+                        {
+                            "type": 0,
+                            "name": "rules",
+                            "messages": [
+                                { "content": "be nice", "username": "m", "avatar": "", "sentAt": "2020-01-01T00:00:00Z" }
+                            ]
+                        }
+                    ]
+                }
+            }"#,
+            true,
+        );
+
+        // The messages of a channel known to be hidden are skipped, but those
+        // read before the channel is known to be hidden are emitted.
+        assert_eq!(summary, [
+            "message 1 0 a hi",
+            "channel 0 general 0 true",
+            "channel 1 staff 0 false",
+            "category 1 Text channels true",
+            "message 2 0 m psst",
+            "channel 0 mod-chat 0 true",
+            "category 2 Moderation false",
+            format!("message {OTHER_CHANNELS_CATEGORY} 0 m be nice").as_str(),
+            "channel 0 rules 0 true",
+            format!("category {OTHER_CHANNELS_CATEGORY} {OTHER_CHANNELS} true").as_str(),
+        ]);
+    }
+}
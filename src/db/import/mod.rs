@@ -0,0 +1,564 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Instant;
+use std::{fmt, thread};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::Deserializer;
+use tracing::info;
+
+use crate::db::visibility::{Facts, ImportConfig};
+use crate::db::Error;
+use crate::Message;
+
+mod chat_exporter;
+mod discord_backup;
+
+use chat_exporter::ChatExporter;
+use discord_backup::DiscordBackup;
+
+/// Name of the category that collects the channels that aren't in any
+/// category.
+pub(crate) const OTHER_CHANNELS: &str = "Other channels";
+
+/// Number of the "Other channels" category, which comes after every other
+/// category.
+pub(crate) const OTHER_CHANNELS_CATEGORY: u64 = i64::MAX as u64;
+
+/// A single unit of content read from a backup file.
+///
+/// Categories and channels are identified by their position in the backup
+/// (e.g. the category index assigned by the caller, and the channel index
+/// within its file) so that a [`Sink`] can map them to database rows regardless
+/// of the order in which their fields appear in the JSON.
+///
+/// Categories and channels are emitted once they have been read completely,
+/// along with whether `@everyone` can view them and their Discord id if the
+/// backup has it, which identifies them across backups.
+#[derive(Debug)]
+pub(crate) enum Record {
+    Category {
+        category: u64,
+        id: Option<u64>,
+        name: String,
+        public: bool,
+    },
+    Channel {
+        category: u64,
+        channel: u64,
+        id: Option<u64>,
+        channel_type: u64,
+        name: String,
+        public: bool,
+    },
+    Message {
+        category: u64,
+        channel: u64,
+        message: Message,
+    },
+}
+
+/// Receives records as they are deserialized.
+pub(crate) trait Sink {
+    fn record(&mut self, record: Record) -> Result<(), Error>;
+}
+
+/// A backup format, read one file at a time.
+pub(crate) trait Importer: Sync {
+    /// A file of the backup, along with where its content belongs.
+    type File: AsRef<Path> + Sync;
+
+    /// Lists the files of the backup in `path` in import order, emitting the
+    /// records that aren't read from any file.
+    fn list_files(&self, path: &Path, sink: &mut impl Sink) -> Result<Vec<Self::File>, Error>;
+
+    /// Deserializes a file, streaming its content into `emitter`.
+    fn read_file<'de, D: Deserializer<'de>, S: Sink>(
+        &self,
+        file: &Self::File,
+        deserializer: D,
+        emitter: &mut Emitter<S>,
+    ) -> Result<(), D::Error>;
+}
+
+/// The backup formats that can be imported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BackupFormat {
+    /// A backup of the whole server made with discord-backup.
+    DiscordBackup,
+    /// One file per channel, exported with DiscordChatExporter.
+    DiscordChatExporter,
+}
+
+impl fmt::Display for BackupFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupFormat::DiscordBackup => f.write_str("discord-backup"),
+            BackupFormat::DiscordChatExporter => f.write_str("DiscordChatExporter"),
+        }
+    }
+}
+
+/// Detects the format of the backup in `path`, from its layout and the keys of
+/// its top-level `.json` files.
+pub(crate) fn detect_format(path: &Path) -> Result<BackupFormat, Error> {
+    if path.join("categories").exists() {
+        return Ok(BackupFormat::DiscordBackup);
+    }
+
+    let Some(file) = list_json_files(path)?.into_iter().next() else {
+        return Err(Error::Generic(format!(
+            "{path:?} contains neither a backup `.json` file nor a `categories` directory."
+        )));
+    };
+
+    match find_key(&file, &["channels", "guild"])? {
+        Some("channels") => Ok(BackupFormat::DiscordBackup),
+        Some(_) => Ok(BackupFormat::DiscordChatExporter),
+        None => Err(Error::Generic(format!("{file:?} isn't a backup in a known format."))),
+    }
+}
+
+fn other_channels_facts() -> Facts {
+    Facts { name: Some(OTHER_CHANNELS.to_string()), public: Some(true) }
+}
+
+/// Finds the backup directories, i.e. the directories inside `path`, sorted by
+/// name.
+pub(crate) fn find_backup_dirs(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+// Lists the `.json` files at the top level of `path`, sorted by name.
+fn list_json_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("json") {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+// Reads the keys of the top-level object of a JSON file until one of `keys` is
+// found, without reading the rest of the file.
+fn find_key(path: &Path, keys: &[&'static str]) -> Result<Option<&'static str>, Error> {
+    struct KeyFinder<'a> {
+        keys: &'a [&'static str],
+        found: &'a mut Option<&'static str>,
+    }
+
+    impl<'de> Visitor<'de> for KeyFinder<'_> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an object")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+            while let Some(key) = map.next_key::<String>()? {
+                if let Some(&key) = self.keys.iter().find(|&&k| k == key) {
+                    *self.found = Some(key);
+                    // Stop reading.
+                    return Err(de::Error::custom("key found"));
+                }
+                map.next_value::<IgnoredAny>()?;
+            }
+
+            Ok(())
+        }
+    }
+
+    let reader = File::open(path).map_err(|e| Error::LoadChannel(path.to_path_buf(), e))?;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let mut found = None;
+
+    let result = deserializer.deserialize_map(KeyFinder { keys, found: &mut found });
+    match found {
+        Some(key) => Ok(Some(key)),
+        None => {
+            result.map(|_| None).or_else(|e| if e.is_data() { Ok(None) } else { Err(e.into()) })
+        },
+    }
+}
+
+/// Deserializes a backup file, streaming its content into `sink` one message
+/// at a time.
+///
+/// The messages of channels that `config` is known to hide by the time they
+/// are read are skipped.
+///
+/// Returns the number of messages read.
+fn import_file<I: Importer>(
+    importer: &I,
+    file: &I::File,
+    config: &ImportConfig,
+    sink: &mut impl Sink,
+    progress: &ProgressBar,
+) -> Result<u64, Error> {
+    let path = file.as_ref();
+    let reader = File::open(path).map_err(|e| Error::LoadChannel(path.to_path_buf(), e))?;
+    let start = Instant::now();
+
+    let reader = BufReader::new(progress.wrap_read(reader));
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let mut emitter = Emitter { sink, config, error: None, messages: 0 };
+
+    let result = importer.read_file(file, &mut deserializer, &mut emitter);
+
+    // An error raised by the sink takes precedence over the deserialization
+    // error it caused.
+    if let Some(e) = emitter.error {
+        return Err(e);
+    }
+    result?;
+    deserializer.end()?;
+
+    progress.suspend(|| {
+        info!("Read {} messages from {path:?} in {:.1?}", emitter.messages, start.elapsed())
+    });
+
+    Ok(emitter.messages)
+}
+
+/// Imports every file of the backup directory into `sink`, skipping the
+/// messages of hidden channels where possible (see [`import_file`]).
+///
+/// The format of the backup is detected from its files. Files are parsed in
+/// parallel and their records sent to `sink` in batches over bounded channels,
+/// so that memory usage doesn't depend on the size of the backup. Records
+/// arrive in the order of the files regardless of which parser finishes first,
+/// so that importing the same backup always yields the same rowids.
+pub(crate) fn import(
+    path: &Path,
+    config: &ImportConfig,
+    sink: &mut impl Sink,
+) -> Result<u64, Error> {
+    let format = detect_format(path)?;
+    info!("Importing {format} backup from {path:?}.");
+
+    match format {
+        BackupFormat::DiscordBackup => import_files(&DiscordBackup, path, config, sink),
+        BackupFormat::DiscordChatExporter => import_files(&ChatExporter, path, config, sink),
+    }
+}
+
+fn import_files<I: Importer>(
+    importer: &I,
+    path: &Path,
+    config: &ImportConfig,
+    sink: &mut impl Sink,
+) -> Result<u64, Error> {
+    let files = importer.list_files(path, sink)?;
+
+    let mut total_size = 0;
+    for file in &files {
+        total_size += file.as_ref().metadata()?.len();
+    }
+
+    let progress = ProgressBar::new(total_size).with_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta} left)",
+        )
+        .expect("valid progress bar template"),
+    );
+
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get).min(files.len());
+    let next_file = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+
+    // The batches waiting for the writer are shared among the files being
+    // parsed.
+    let bound = (CHANNEL_BOUND / workers.max(1)).max(1);
+
+    let result = thread::scope(|scope| {
+        // Each file is sent over its own channel, announced to the writer along
+        // with the position of the file.
+        let (files_tx, files_rx) = mpsc::channel::<(usize, Receiver<Vec<Record>>)>();
+
+        let parsers = (0..workers)
+            .map(|_| {
+                let files_tx = files_tx.clone();
+                let (files, next_file, aborted, progress) =
+                    (&files, &next_file, &aborted, &progress);
+
+                scope.spawn(move || {
+                    let mut messages = 0;
+
+                    while !aborted.load(Ordering::Relaxed) {
+                        let index = next_file.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            break;
+                        };
+
+                        let (tx, rx) = mpsc::sync_channel(bound);
+                        files_tx.send((index, rx)).map_err(|_| writer_stopped())?;
+                        let mut sender = BatchSender { tx, batch: Vec::new() };
+
+                        match import_file(importer, file, config, &mut sender, progress) {
+                            Ok(count) => messages += count,
+                            Err(e) => {
+                                aborted.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            },
+                        }
+                        sender.flush()?;
+                    }
+
+                    Ok::<_, Error>(messages)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only the parsers hold senders now, so the channel closes when they're
+        // all done.
+        drop(files_tx);
+
+        // Parsers blocked on a full channel return once the writer hangs up.
+        let written = write_in_order(files_rx, sink);
+        if written.is_err() {
+            aborted.store(true, Ordering::Relaxed);
+        }
+
+        let parsed = parsers.into_iter().try_fold(0, |total, parser| {
+            let messages = parser
+                .join()
+                .map_err(|_| Error::Generic("backup parser thread panicked".to_string()))??;
+            Ok::<_, Error>(total + messages)
+        });
+
+        // If the sink failed, parsers only report that the channel was closed.
+        written.and(parsed)
+    });
+
+    progress.finish_and_clear();
+
+    result
+}
+
+// Writes the records of each file to `sink`, waiting for the files in the order
+// of their positions.
+fn write_in_order(
+    files: Receiver<(usize, Receiver<Vec<Record>>)>,
+    sink: &mut impl Sink,
+) -> Result<(), Error> {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    loop {
+        let Some(records) = pending.remove(&next) else {
+            match files.recv() {
+                Ok((index, records)) => {
+                    pending.insert(index, records);
+                    continue;
+                },
+                // Every file has been written.
+                Err(_) => return Ok(()),
+            }
+        };
+
+        records.iter().flatten().try_for_each(|record| sink.record(record))?;
+        next += 1;
+    }
+}
+
+fn writer_stopped() -> Error {
+    Error::Generic("database writer stopped".to_string())
+}
+
+// Number of records sent to the writer at once.
+const RECORDS_PER_BATCH: usize = 1000;
+
+// Number of record batches that can be waiting for the writer, across files.
+const CHANNEL_BOUND: usize = 64;
+
+// Batches records and sends them to the writer thread.
+struct BatchSender {
+    tx: SyncSender<Vec<Record>>,
+    batch: Vec<Record>,
+}
+
+impl BatchSender {
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(RECORDS_PER_BATCH));
+        self.tx.send(batch).map_err(|_| writer_stopped())
+    }
+}
+
+impl Sink for BatchSender {
+    fn record(&mut self, record: Record) -> Result<(), Error> {
+        self.batch.push(record);
+
+        if self.batch.len() >= RECORDS_PER_BATCH {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Forwards records to the sink, holding on to the first error it returns so
+/// that it can be reported instead of the opaque deserialization error used to
+/// abort parsing.
+pub(crate) struct Emitter<'a, S> {
+    sink: &'a mut S,
+    config: &'a ImportConfig,
+    error: Option<Error>,
+    messages: u64,
+}
+
+impl<S: Sink> Emitter<'_, S> {
+    fn emit<E: de::Error>(&mut self, record: Record) -> Result<(), E> {
+        if matches!(record, Record::Message { .. }) {
+            self.messages += 1;
+        }
+
+        self.sink.record(record).map_err(|e| {
+            self.error = Some(e);
+            E::custom("import aborted")
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    pub(crate) struct Records(pub(crate) Vec<Record>);
+
+    impl Sink for Records {
+        fn record(&mut self, record: Record) -> Result<(), Error> {
+            self.0.push(record);
+            Ok(())
+        }
+    }
+
+    /// Describes each record on a line.
+    pub(crate) fn summarize(records: &[Record]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| match record {
+                Record::Category { category, name, public, .. } => {
+                    format!("category {category} {name} {public}")
+                },
+                Record::Channel { channel, name, channel_type, public, .. } => {
+                    format!("channel {channel} {name} {channel_type} {public}")
+                },
+                Record::Message { category, channel, message } => {
+                    format!("message {category} {channel} {} {}", message.username, message.content)
+                },
+            })
+            .collect()
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    // Imports a fixture, sorting the summary to compare it regardless of the
+    // order of the files. Channels exported in several parts are emitted once per
+    // part, which the writer handles as updates.
+    fn import_fixture(name: &str, config: &ImportConfig) -> Vec<String> {
+        let mut records = Records::default();
+        import(&fixture(name), config, &mut records).expect("Couldn't import fixture");
+
+        let mut summary = summarize(&records.0);
+        summary.sort();
+        summary.dedup();
+        summary
+    }
+
+    #[test]
+    fn test_detect_format() {
+        for (name, format) in [
+            ("discord-backup", BackupFormat::DiscordBackup),
+            ("discord-backup-split", BackupFormat::DiscordBackup),
+            ("discord-chat-exporter", BackupFormat::DiscordChatExporter),
+        ] {
+            assert_eq!(detect_format(&fixture(name)).unwrap(), format, "{name}");
+        }
+
+        assert!(detect_format(&fixture("discord-chat-exporter/media")).is_err());
+    }
+
+    #[test]
+    fn test_import_fixtures() {
+        let config = ImportConfig::default();
+
+        // Both layouts of discord-backup hold the same server.
+        let backup = import_fixture("discord-backup", &config);
+        assert_eq!(backup, import_fixture("discord-backup-split", &config));
+        assert_eq!(backup, [
+            "category 1 Text channels true",
+            "category 2 Moderation false",
+            format!("category {OTHER_CHANNELS_CATEGORY} {OTHER_CHANNELS} true").as_str(),
+            "channel 0 general 0 true",
+            "channel 0 mod-chat 0 true",
+            "channel 0 rules 0 true",
+            "channel 1 voice 2 true",
+            "message 1 0 alice hi &#60;3",
+            "message 1 0 bob hello <img class=\"emote\" alt=\"wave\" src=\"https://cdn.discordapp.com/emojis/1234.png\"/>",
+            format!("message {OTHER_CHANNELS_CATEGORY} 0 mod be nice").as_str(),
+        ]);
+
+        let exporter = import_fixture("discord-chat-exporter", &config);
+        assert_eq!(exporter, [
+            "category 1 Text channels true",
+            "category 2 Moderation true",
+            format!("category {OTHER_CHANNELS_CATEGORY} {OTHER_CHANNELS} true").as_str(),
+            "channel 0 general 0 true",
+            "channel 0 mod-chat 0 true",
+            "channel 0 rules 0 true",
+            "channel 1 voice 2 true",
+            "message 1 0 alice hi &#60;3",
+            "message 1 0 alice second part",
+            "message 1 0 bob hello :wave:\nhttps://cdn.discordapp.com/attachments/1/2/cat.png",
+            "message 2 0 mod psst",
+            format!("message {OTHER_CHANNELS_CATEGORY} 0 mod be nice").as_str(),
+        ]);
+
+        // Records are written in the order of the files, however fast each one
+        // is parsed.
+        let mut records = Records::default();
+        import(&fixture("discord-backup-split"), &config, &mut records).unwrap();
+        let first = summarize(&records.0);
+        for _ in 0..10 {
+            let mut records = Records::default();
+            import(&fixture("discord-backup-split"), &config, &mut records).unwrap();
+            assert_eq!(summarize(&records.0), first);
+        }
+
+        // Messages of excluded channels are skipped.
+        let config = ImportConfig { exclude_categories: vec!["Moderation".to_string()], ..config };
+        let exporter = import_fixture("discord-chat-exporter", &config);
+        assert!(!exporter.iter().any(|line| line.contains("psst")));
+    }
+}
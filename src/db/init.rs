@@ -1,88 +1,527 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::Duration;
+
+use chrono::Utc;
+use indicatif::{ProgressBar, ProgressStyle};
+use rusqlite::types::ToSql;
 use rusqlite::Connection;
-use tracing::info;
+use tracing::{debug, info};
 
-use crate::db::{self, PAGE_SIZE};
-use crate::{Category, Channel, Message};
+use crate::anonymise::Anonymiser;
+use crate::db::import::{Record, Sink, OTHER_CHANNELS_CATEGORY};
+use crate::db::{self, links, migrate, ImportConfig, PAGE_SIZE};
+use crate::Message;
 
-pub(crate) fn insert_messages(
-    messages: Vec<Message>,
-    channel_id: i64,
-    db: &Connection,
-) -> Result<(), db::Error> {
-    let mut stmt = db.prepare(
-        r#"
-        INSERT INTO messages (content, username, avatar, sent_at, channel_id)
-        VALUES (?1, ?2, ?3, ?4, ?5);
-        "#,
-    )?;
+/// Number of messages inserted per transaction while building the archive.
+const BATCH_SIZE: u64 = 50_000;
+
+/// Number of rows inserted by a single `INSERT` statement.
+const ROWS_PER_INSERT: usize = 100;
+
+/// Number of messages copied to the FTS table per statement.
+const FTS_CHUNK_SIZE: u64 = 50_000;
+
+/// Writes imported records into the archive database, inserting messages
+/// [`ROWS_PER_INSERT`] at a time and committing every [`BATCH_SIZE`] messages.
+///
+/// Several backups can be written one after the other (see
+/// [`Writer::begin_source`]), in which case their categories, channels and
+/// messages are merged once they have all been read.
+pub(crate) struct Writer<'a> {
+    db: &'a Connection,
+    config: &'a ImportConfig,
+    anonymiser: Option<Anonymiser>,
+    // `source_id` of the backup being written.
+    source: i64,
+    // Added to the category numbers of the backup being written, so that they
+    // don't collide with those of the previous backups.
+    category_offset: i64,
+    // Maps a channel's position in the backup being written to its
+    // `channel_id`.
+    channels: HashMap<(u64, u64), i64>,
+    // Each category, by `category_id`.
+    categories: HashMap<i64, Node>,
+    // Each channel, by `channel_id`.
+    channel_facts: HashMap<i64, Node>,
+    rows: Vec<(Message, i64)>,
+    pending: u64,
+    messages: u64,
+}
 
-    for message in messages {
-        stmt.execute((
-            message.content.as_ref(),
-            message.username,
-            message.avatar,
-            message.sent_at,
-            channel_id,
-        ))?;
+// A category or channel, as read from a backup.
+struct Node {
+    source: i64,
+    // `category_id` of a channel, and 0 for categories.
+    category: i64,
+    id: Option<u64>,
+    name: String,
+    // Whether `@everyone` can view it.
+    public: bool,
+}
+
+impl Node {
+    // Whether two categories or channels of different backups are the same.
+    // Their Discord ids are compared if both backups have them, and their names
+    // (and categories) otherwise.
+    fn is_same(&self, other: &Node) -> bool {
+        match (self.id, other.id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            _ => self.name == other.name && self.category == other.category,
+        }
     }
 
-    Ok(())
+    // Folds a later copy into this one. The latest name is kept, and copies are
+    // only public if they all are.
+    fn absorb(&mut self, later: Node) {
+        self.name = later.name;
+        self.public &= later.public;
+    }
 }
 
-pub(crate) fn insert_channel(
-    channel: Channel,
-    category_id: i64,
-    db: &Connection,
-) -> Result<(), db::Error> {
-    if channel.channel_type != 0 {
-        info!("Skipping channel \"{}\"...", channel.name);
-        return Ok(());
+// Pairs the categories or channels that are the same as one of an earlier
+// backup with it, in the order of their ids. Each of them is paired with at
+// most one of every later backup.
+fn find_merges(nodes: &HashMap<i64, Node>) -> Vec<(i64, i64)> {
+    let mut ids = nodes.keys().copied().collect::<Vec<_>>();
+    ids.sort_unstable();
+
+    let mut merges = Vec::new();
+    let mut merged = HashSet::new();
+    let mut paired = HashSet::new();
+
+    for (i, id) in ids.iter().enumerate() {
+        let node = &nodes[id];
+        let kept = ids[..i].iter().find(|kept| {
+            let earlier = &nodes[*kept];
+            earlier.source < node.source
+                && !merged.contains(*kept)
+                && !paired.contains(&(**kept, node.source))
+                && earlier.is_same(node)
+        });
+
+        if let Some(&kept) = kept {
+            merged.insert(*id);
+            paired.insert((kept, node.source));
+            merges.push((kept, *id));
+        }
     }
 
-    info!("Inserting channel \"{}\"...", channel.name);
-    db.execute("BEGIN TRANSACTION", [])?;
+    merges
+}
 
-    db.execute(
-        r#"
-        INSERT INTO channels (channel_type, name, category_id)
-        VALUES (?1, ?2, ?3);
-        "#,
-        (channel.channel_type, channel.name, category_id),
-    )?;
-    let channel_id = db.last_insert_rowid();
+impl<'a> Writer<'a> {
+    pub(crate) fn new(db: &'a Connection, config: &'a ImportConfig) -> Result<Self, db::Error> {
+        db.execute("BEGIN TRANSACTION", [])?;
+        Ok(Self {
+            db,
+            config,
+            anonymiser: Anonymiser::new(&config.anonymise)?,
+            source: 0,
+            category_offset: 0,
+            channels: HashMap::new(),
+            categories: HashMap::new(),
+            channel_facts: HashMap::new(),
+            rows: Vec::with_capacity(ROWS_PER_INSERT),
+            pending: 0,
+            messages: 0,
+        })
+    }
+
+    /// Starts writing the backup named `name`. Records refer to the categories
+    /// and channels of the backup that was last started.
+    pub(crate) fn begin_source(&mut self, name: &str) -> Result<(), db::Error> {
+        self.insert_rows()?;
+
+        self.db.execute("INSERT INTO sources (name) VALUES (?1)", [name])?;
+        self.source = self.db.last_insert_rowid();
+        self.category_offset = self.db.query_row(
+            "SELECT COALESCE(MAX(category_id), 0) FROM categories WHERE category_id != ?1",
+            [OTHER_CHANNELS_CATEGORY],
+            |row| row.get(0),
+        )?;
+        self.channels.clear();
 
-    if let Some(messages) = channel.messages {
-        insert_messages(messages, channel_id, db)?;
+        Ok(())
     }
 
-    db.execute("COMMIT", [])?;
+    /// Commits the last batch, merges the backups and removes the channels
+    /// that aren't text channels or that the import configuration hides, along
+    /// with the categories left empty.
+    pub(crate) fn finish(mut self) -> Result<(), db::Error> {
+        self.insert_rows()?;
+        self.merge()?;
 
-    Ok(())
+        let hidden = self.hidden_channels();
+
+        self.db.execute_batch(
+            r#"
+            CREATE TEMP TABLE hidden_channels (channel_id INTEGER NOT NULL PRIMARY KEY);
+            INSERT INTO hidden_channels SELECT channel_id FROM channels WHERE channel_type != 0;
+            "#,
+        )?;
+        {
+            let mut stmt = self
+                .db
+                .prepare("INSERT OR IGNORE INTO hidden_channels (channel_id) VALUES (?1)")?;
+            for channel_id in &hidden {
+                stmt.execute([channel_id])?;
+            }
+        }
+
+        // The messages of hidden channels may have been inserted before they
+        // were known to be hidden. Overwrite them when deleting them, so that
+        // they can't be recovered from the archive file.
+        self.db.execute_batch(
+            r#"
+            PRAGMA secure_delete = ON;
+            DELETE FROM messages WHERE channel_id IN (SELECT channel_id FROM hidden_channels);
+            DELETE FROM channels WHERE channel_id IN (SELECT channel_id FROM hidden_channels);
+            DELETE FROM categories WHERE category_id NOT IN (SELECT category_id FROM channels);
+            DROP TABLE hidden_channels;
+            PRAGMA secure_delete = OFF;
+            COMMIT;
+            "#,
+        )?;
+
+        info!("Inserted {} messages.", self.messages);
+        info!("Left out {} channels hidden from @everyone or by the configuration.", hidden.len());
+
+        Ok(())
+    }
+
+    // Merges the categories and channels that several backups hold into those
+    // of the earliest backup (see `Node::is_same`), dropping the messages of
+    // later backups that were sent in the same channel at the same time by the
+    // same user as a message of an earlier backup.
+    fn merge(&mut self) -> Result<(), db::Error> {
+        for (kept, merged) in find_merges(&self.categories) {
+            let node = self.categories.remove(&merged).expect("merged category exists");
+
+            self.db.execute(
+                "UPDATE channels SET category_id = ?1 WHERE category_id = ?2",
+                (kept, merged),
+            )?;
+            self.db.execute("DELETE FROM categories WHERE category_id = ?1", [merged])?;
+            self.db.execute(
+                "UPDATE categories SET name = ?1 WHERE category_id = ?2",
+                (&node.name, kept),
+            )?;
+
+            for channel in self.channel_facts.values_mut().filter(|c| c.category == merged) {
+                channel.category = kept;
+            }
+            if let Some(category) = self.categories.get_mut(&kept) {
+                category.absorb(node);
+            }
+        }
+
+        let merges = find_merges(&self.channel_facts);
+        if merges.is_empty() {
+            return Ok(());
+        }
+
+        // Duplicates are looked up among the messages of the kept channel.
+        self.db
+            .execute("CREATE INDEX messages_merge ON messages(channel_id, sent_at, username)", [
+            ])?;
+
+        let mut duplicates = 0;
+        for &(kept, merged) in &merges {
+            let node = self.channel_facts.remove(&merged).expect("merged channel exists");
+
+            duplicates += self.db.execute(
+                r#"
+                DELETE FROM messages
+                WHERE channel_id = ?2 AND EXISTS (
+                    SELECT 1 FROM messages AS kept
+                    WHERE kept.channel_id = ?1
+                        AND kept.sent_at = messages.sent_at
+                        AND kept.username = messages.username
+                );
+                "#,
+                (kept, merged),
+            )?;
+            self.db.execute(
+                "UPDATE messages SET channel_id = ?1 WHERE channel_id = ?2",
+                (kept, merged),
+            )?;
+            self.db.execute("DELETE FROM channels WHERE channel_id = ?1", [merged])?;
+            self.db.execute(
+                "UPDATE channels SET name = ?1 WHERE channel_id = ?2",
+                (&node.name, kept),
+            )?;
+
+            if let Some(channel) = self.channel_facts.get_mut(&kept) {
+                channel.absorb(node);
+            }
+        }
+
+        self.db.execute("DROP INDEX messages_merge", [])?;
+        self.messages -= duplicates as u64;
+
+        info!(
+            "Merged {} channels held by several backups, leaving out {duplicates} duplicate \
+             messages.",
+            merges.len()
+        );
+
+        Ok(())
+    }
+
+    // Lists the channels that the import configuration hides.
+    fn hidden_channels(&self) -> HashSet<i64> {
+        self.channel_facts
+            .iter()
+            .filter(|(_, Node { category, name, public, .. })| {
+                let (category_name, category_public) = self
+                    .categories
+                    .get(category)
+                    .map_or(("", true), |category| (category.name.as_str(), category.public));
+
+                if self.config.is_visible(category_name, category_public, name, *public) {
+                    return false;
+                }
+
+                debug!("Leaving out channel \"{name}\" of category \"{category_name}\".");
+                true
+            })
+            .map(|(&channel_id, _)| channel_id)
+            .collect()
+    }
+
+    // Maps a category number of the backup being written to its `category_id`.
+    // The "Other channels" categories of every backup are the same.
+    fn category_id(&self, category: u64) -> i64 {
+        if category == OTHER_CHANNELS_CATEGORY {
+            OTHER_CHANNELS_CATEGORY as i64
+        } else {
+            category as i64 + self.category_offset
+        }
+    }
+
+    // Channel rows are created the first time a channel is referenced, and
+    // completed once its name and type have been read.
+    fn channel_id(&mut self, category: u64, channel: u64) -> Result<i64, db::Error> {
+        if let Some(&channel_id) = self.channels.get(&(category, channel)) {
+            return Ok(channel_id);
+        }
+
+        let category_id = self.category_id(category);
+
+        self.db.execute(
+            r#"INSERT OR IGNORE INTO categories (category_id, name) VALUES (?1, '');"#,
+            [category_id],
+        )?;
+        self.db.execute(
+            r#"
+            INSERT INTO channels (channel_type, name, category_id)
+            VALUES (-1, NULL, ?1);
+            "#,
+            [category_id],
+        )?;
+
+        let channel_id = self.db.last_insert_rowid();
+        self.channels.insert((category, channel), channel_id);
+
+        Ok(channel_id)
+    }
+
+    // Inserts the buffered messages with a single multi-row statement.
+    fn insert_rows(&mut self) -> Result<(), db::Error> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = String::from(
+            "INSERT INTO messages (content, username, avatar, sent_at, channel_id, source_id) \
+             VALUES ",
+        );
+        for i in 0..self.rows.len() {
+            let n = i * 6;
+            let separator = if i == 0 { "" } else { ", " };
+            write!(
+                query,
+                "{separator}(?{}, ?{}, ?{}, ?{}, ?{}, ?{})",
+                n + 1,
+                n + 2,
+                n + 3,
+                n + 4,
+                n + 5,
+                n + 6
+            )
+            .map_err(|e| db::Error::Generic(e.to_string()))?;
+        }
+
+        let params = self
+            .rows
+            .iter()
+            .flat_map(|(message, channel_id)| -> [&dyn ToSql; 6] {
+                [
+                    &message.content.0,
+                    &message.username,
+                    &message.avatar,
+                    &message.sent_at,
+                    channel_id,
+                    &self.source,
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        self.db.prepare_cached(&query)?.execute(params.as_slice())?;
+        self.rows.clear();
+
+        Ok(())
+    }
 }
 
-pub(crate) fn insert_category(category: Category, db: &Connection) -> Result<(), db::Error> {
-    info!("Inserting category \"{}\"...", category.name);
+impl Sink for Writer<'_> {
+    fn record(&mut self, record: Record) -> Result<(), db::Error> {
+        match record {
+            Record::Category { category, id, name, public } => {
+                debug!("Inserting category \"{name}\"...");
+
+                let category_id = self.category_id(category);
+                self.db.execute(
+                    r#"
+                    INSERT INTO categories (category_id, name) VALUES (?1, ?2)
+                    ON CONFLICT(category_id) DO UPDATE SET name = excluded.name;
+                    "#,
+                    (category_id, &name),
+                )?;
+                self.categories.insert(category_id, Node {
+                    source: self.source,
+                    category: 0,
+                    id,
+                    name,
+                    public,
+                });
+            },
+            Record::Channel { category, channel, id, channel_type, name, public } => {
+                if channel_type != 0 {
+                    debug!("Skipping channel \"{name}\"...");
+                } else {
+                    debug!("Inserted channel \"{name}\".");
+                }
 
-    db.execute(r#"INSERT INTO categories (name) VALUES (?1);"#, [category.name])?;
-    let category_id = db.last_insert_rowid();
+                let channel_id = self.channel_id(category, channel)?;
+                self.db.execute(
+                    r#"UPDATE channels SET channel_type = ?1, name = ?2 WHERE channel_id = ?3;"#,
+                    (channel_type, &name, channel_id),
+                )?;
+                let category = self.category_id(category);
+                self.channel_facts.insert(channel_id, Node {
+                    source: self.source,
+                    category,
+                    id,
+                    name,
+                    public,
+                });
+            },
+            Record::Message { category, channel, mut message } => {
+                if let Some(anonymiser) = &mut self.anonymiser {
+                    anonymiser.anonymise(&mut message);
+                }
 
-    for channel in category.children {
-        insert_channel(channel, category_id, db)?;
+                let channel_id = self.channel_id(category, channel)?;
+                self.rows.push((message, channel_id));
+
+                if self.rows.len() == ROWS_PER_INSERT {
+                    self.insert_rows()?;
+                }
+
+                self.pending += 1;
+                self.messages += 1;
+
+                if self.pending == BATCH_SIZE {
+                    self.insert_rows()?;
+                    self.db.execute_batch("COMMIT; BEGIN TRANSACTION;")?;
+                    self.pending = 0;
+                }
+            },
+        }
+
+        Ok(())
     }
-    Ok(())
+}
+
+fn spinner(message: &'static str) -> ProgressBar {
+    let spinner = ProgressBar::new_spinner()
+        .with_style(
+            ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}")
+                .expect("valid progress bar template"),
+        )
+        .with_message(message);
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
 }
 
 pub(crate) fn cache(db: &Connection) -> Result<(), db::Error> {
     info!("Populating FTS table...");
-    db.execute(
+
+    // Copy messages in chunks of consecutive rowids, so that progress can be
+    // reported.
+    let max_rowid: u64 =
+        db.query_row("SELECT COALESCE(MAX(rowid), 0) FROM messages", [], |row| row.get(0))?;
+    let progress = ProgressBar::new(max_rowid).with_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{wide_bar}] {pos}/{len} ({eta} left)",
+        )
+        .expect("valid progress bar template"),
+    );
+
+    db.execute("BEGIN TRANSACTION", [])?;
+    for start in (0..max_rowid).step_by(FTS_CHUNK_SIZE as usize) {
+        db.execute(
+            r#"
+            INSERT INTO messages_fts (content, username, avatar, messages_rowid)
+            SELECT content, username, avatar, rowid FROM messages
+            WHERE rowid > ?1 AND rowid <= ?2;
+            "#,
+            [start, start + FTS_CHUNK_SIZE],
+        )?;
+        progress.set_position((start + FTS_CHUNK_SIZE).min(max_rowid));
+    }
+    db.execute("COMMIT", [])?;
+    progress.finish_and_clear();
+
+    info!("Caching page numbers...");
+    let progress = spinner("Caching page numbers...");
+    cache_pages(db)?;
+    progress.finish_and_clear();
+
+    info!("Aggregating messages...");
+    let progress = spinner("Aggregating messages...");
+    cache_aggregates(db)?;
+    progress.finish_and_clear();
+
+    info!("Indexing links...");
+    let progress = spinner("Indexing links...");
+    links::extract(db)?;
+    progress.finish_and_clear();
+
+    info!("Creating indices...");
+    let progress = spinner("Creating indices...");
+    db.execute_batch(include_str!("migrations/indexes.sql"))?;
+    progress.finish_and_clear();
+
+    // Leave WAL mode so that the archive is a single file that can be served
+    // from a read-only mount.
+    db.execute_batch(
         r#"
-        INSERT INTO messages_fts (content, username, avatar, messages_rowid)
-        SELECT content, username, avatar, rowid FROM messages;
+        PRAGMA journal_mode = DELETE;
+        PRAGMA synchronous = FULL;
+        ANALYZE;
         "#,
-        [],
     )?;
 
-    info!("Caching page numbers...");
+    Ok(())
+}
+
+/// Numbers the pages of every channel, replacing the previous numbers, e.g.
+/// once messages were redacted.
+pub(crate) fn cache_pages(db: &Connection) -> Result<(), db::Error> {
+    db.execute_batch("BEGIN TRANSACTION; DELETE FROM messages_pages;")?;
 
     // Algorithm of this query:
     // - group messages by channel_id
@@ -96,18 +535,203 @@ pub(crate) fn cache(db: &Connection) -> Result<(), db::Error> {
         SELECT ((
             ROW_NUMBER() OVER (
                 PARTITION BY channel_id
-                ORDER BY sent_at DESC
+                ORDER BY sent_at DESC, rowid DESC
             )
         ) - 1) / ?1, messages.rowid, messages.channel_id
         FROM messages;
         "#,
         [PAGE_SIZE],
     )?;
+    db.execute_batch("COMMIT;")?;
+
+    Ok(())
+}
+
+/// Recomputes the aggregate tables of the statistics and calendar pages from
+/// the messages.
+pub(crate) fn cache_aggregates(db: &Connection) -> Result<(), db::Error> {
+    db.execute_batch(&format!(
+        "BEGIN TRANSACTION; {} COMMIT;",
+        include_str!("migrations/aggregates.sql")
+    ))?;
 
     Ok(())
 }
 
 pub(crate) fn initialize(db: &Connection) -> Result<(), db::Error> {
-    db.execute_batch(include_str!("migrations/init.sql"))?;
+    // Nothing is lost if the build is interrupted, as it would have to be
+    // started over anyway, so trade durability for speed.
+    db.execute_batch(
+        r#"
+        PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = OFF;
+        PRAGMA temp_store = MEMORY;
+        PRAGMA cache_size = -262144;
+        "#,
+    )?;
+
+    // Indices are created once the tables are populated, see `cache`. Those
+    // that migrations of existing archives create are dropped until then.
+    migrate::migrate(db)?;
+    db.execute_batch(
+        r#"
+        DROP INDEX IF EXISTS messages_users;
+        DROP INDEX IF EXISTS links_domains;
+        "#,
+    )?;
+
+    Ok(())
+}
+
+/// Records the completed-build marker. Archives without it are never served.
+pub(crate) fn complete(db: &Connection, fingerprint: &str) -> Result<(), db::Error> {
+    db.execute(
+        r#"
+        INSERT INTO metadata (key, value) VALUES
+            ('source_fingerprint', ?1),
+            ('built_at', ?2);
+        "#,
+        (fingerprint, Utc::now().to_rfc3339()),
+    )?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::redact::{self, Redaction};
+    use crate::MessageContent;
+
+    fn category(category: u64, id: Option<u64>, name: &str) -> Record {
+        Record::Category { category, id, name: name.to_string(), public: true }
+    }
+
+    fn channel(category: u64, id: Option<u64>, name: &str) -> Record {
+        let name = name.to_string();
+        Record::Channel { category, channel: 0, id, channel_type: 0, name, public: true }
+    }
+
+    fn message(category: u64, username: &str, minute: u32, content: &str) -> Record {
+        let sent_at = format!("2020-01-01T00:{minute:02}:00Z").parse().unwrap();
+        let message = Message {
+            content: MessageContent::from_text(content),
+            username: username.to_string(),
+            avatar: String::new(),
+            sent_at,
+            rowid: 0,
+            redacted: false,
+        };
+        Record::Message { category, channel: 0, message }
+    }
+
+    fn rows(db: &Connection, query: &str) -> Vec<String> {
+        db.prepare(query)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_merge_backups() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate::migrate(&db).unwrap();
+        let config = ImportConfig::default();
+        let mut writer = Writer::new(&db, &config).unwrap();
+
+        writer.begin_source("2020").unwrap();
+        for record in [
+            message(1, "alice", 0, "hi"),
+            message(1, "bob", 1, "yo"),
+            channel(1, None, "general"),
+            category(1, None, "Text channels"),
+            message(2, "mod", 0, "be nice"),
+            channel(2, Some(5), "rules"),
+            category(2, Some(4), "Info"),
+        ] {
+            writer.record(record).unwrap();
+        }
+
+        // The second backup renamed a category and a channel, and overlaps with
+        // the first one.
+        writer.begin_source("2021").unwrap();
+        for record in [
+            category(1, Some(4), "Information"),
+            channel(1, Some(5), "read-me"),
+            message(1, "mod", 2, "be nicer"),
+            category(2, None, "Text channels"),
+            channel(2, None, "general"),
+            message(2, "bob", 1, "yo"),
+            message(2, "carol", 3, "hey"),
+        ] {
+            writer.record(record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(rows(&db, "SELECT name FROM categories ORDER BY category_id"), [
+            "Text channels",
+            "Information"
+        ]);
+        assert_eq!(rows(&db, "SELECT name FROM channels ORDER BY channel_id"), [
+            "general", "read-me"
+        ]);
+        assert_eq!(
+            rows(
+                &db,
+                r#"
+                SELECT channels.name || ' ' || content || ' ' || sources.name
+                FROM messages
+                JOIN channels USING (channel_id)
+                JOIN sources USING (source_id)
+                ORDER BY channel_id, sent_at
+                "#
+            ),
+            [
+                "general hi 2020",
+                "general yo 2020",
+                "general hey 2021",
+                "read-me be nice 2020",
+                "read-me be nicer 2021"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_pages() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate::migrate(&db).unwrap();
+        db.execute_batch(
+            r#"
+            INSERT INTO categories (category_id, name) VALUES (1, 'Category');
+            INSERT INTO channels (channel_id, channel_type, name, category_id)
+            VALUES (1, 0, 'general', 1);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 150)
+            INSERT INTO messages (content, username, avatar, sent_at, channel_id)
+            SELECT 'hi', IIF(i % 2, 'alice', 'bob'), '', datetime(i, 'unixepoch'), 1 FROM n;
+            "#,
+        )
+        .unwrap();
+
+        let pages = |db: &Connection| {
+            rows(db, "SELECT DISTINCT CAST(page AS TEXT) FROM messages_pages ORDER BY page")
+        };
+        cache_pages(&db).unwrap();
+        assert_eq!(pages(&db), ["0", "1"]);
+
+        // Pages are numbered again once messages are removed.
+        let redaction = Redaction {
+            user: Some("alice".to_string()),
+            channel: None,
+            sent_at: None,
+            pattern: None,
+            placeholder: false,
+            reason: None,
+            added_at: Utc::now(),
+        };
+        assert_eq!(redact::apply(&db, &[redaction], None).unwrap(), 75);
+        cache_pages(&db).unwrap();
+        assert_eq!(pages(&db), ["0"]);
+    }
+}
@@ -0,0 +1,113 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::db::Error;
+use crate::MessageContent;
+
+/// Number of messages whose links are extracted per query.
+const CHUNK_SIZE: u64 = 50_000;
+
+/// Fills the `links` table with the URLs posted in the messages.
+pub(crate) fn extract(db: &Connection) -> Result<(), Error> {
+    let max_rowid: u64 =
+        db.query_row("SELECT COALESCE(MAX(rowid), 0) FROM messages", [], |row| row.get(0))?;
+
+    let mut select = db.prepare(
+        r#"
+        SELECT rowid, channel_id, content FROM messages
+        WHERE rowid > ?1 AND rowid <= ?2 AND content LIKE '%http%'
+        "#,
+    )?;
+    let mut insert = db.prepare(
+        "INSERT INTO links (messages_rowid, channel_id, domain, url) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+
+    db.execute_batch("BEGIN TRANSACTION; DELETE FROM links;")?;
+
+    let mut links = 0;
+    for start in (0..max_rowid).step_by(CHUNK_SIZE as usize) {
+        let mut rows = select.query([start, start + CHUNK_SIZE])?;
+        while let Some(row) = rows.next()? {
+            let (rowid, channel_id): (u64, u64) = (row.get(0)?, row.get(1)?);
+            let content = MessageContent(row.get(2)?);
+
+            for (domain, url) in urls(&content.plain_text()) {
+                insert.execute((rowid, channel_id, domain, url))?;
+                links += 1;
+            }
+        }
+    }
+
+    db.execute("COMMIT", [])?;
+    info!("Indexed {links} links.");
+
+    Ok(())
+}
+
+/// Finds the URLs in the text of a message, along with their domain.
+fn urls(text: &str) -> Vec<(String, &str)> {
+    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s<>"]+"#).unwrap());
+
+    RE.find_iter(text)
+        .filter_map(|found| {
+            let url = trim_url(found.as_str());
+            domain(url).map(|domain| (domain, url))
+        })
+        .collect()
+}
+
+// Leaves out the punctuation that ends the sentence a URL is written in,
+// keeping the closing parentheses of URLs that open them.
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '*', '_', '~']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(rest) if trimmed.matches('(').count() < trimmed.matches(')').count() => rest,
+            _ => trimmed,
+        };
+
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+// The lowercase host of a URL, without `www.`.
+fn domain(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urls() {
+        let text = "route: https://www.YouTube.com/watch?v=abc&t=1s, notes at \
+                    <https://pastebin.com/xyz> (see https://en.wikipedia.org/wiki/Rust_(language)). \
+                    Not a link: http:// nor ftp://example.com";
+
+        assert_eq!(urls(text), [
+            ("youtube.com".to_string(), "https://www.YouTube.com/watch?v=abc&t=1s"),
+            ("pastebin.com".to_string(), "https://pastebin.com/xyz"),
+            ("en.wikipedia.org".to_string(), "https://en.wikipedia.org/wiki/Rust_(language)"),
+        ]);
+    }
+
+    #[test]
+    fn test_domain() {
+        assert_eq!(
+            domain("https://user@clips.twitch.tv:443/a").as_deref(),
+            Some("clips.twitch.tv")
+        );
+        assert_eq!(domain("http://github.com?q").as_deref(), Some("github.com"));
+    }
+}
@@ -0,0 +1,163 @@
+use chrono::Utc;
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::db::{self, links};
+
+/// Migrations of the archive schema, applied in order. The migration at index
+/// `i` brings the schema to version `i + 1`.
+///
+/// Migrations are only ever appended to this list: editing a migration that has
+/// been released would leave existing archives with a different schema than new
+/// ones.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_init.sql"),
+    include_str!("migrations/0002_redacted.sql"),
+    include_str!("migrations/0003_sources.sql"),
+    include_str!("migrations/0004_stats.sql"),
+    include_str!("migrations/0005_messages_users.sql"),
+    include_str!("migrations/0006_messages_days.sql"),
+    include_str!("migrations/0007_links.sql"),
+];
+
+/// Version of the archive schema after all migrations have been applied.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Schema version from which the build fills the `links` table.
+const LINKS_VERSION: u32 = 7;
+
+fn table_exists(db: &Connection, name: &str) -> Result<bool, db::Error> {
+    Ok(db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [name],
+        |row| row.get(0),
+    )?)
+}
+
+/// Returns the schema version of the archive, `0` meaning an empty database.
+pub(crate) fn schema_version(db: &Connection) -> Result<u32, db::Error> {
+    if !table_exists(db, "schema_version")? {
+        return Ok(0);
+    }
+
+    Ok(db.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?)
+}
+
+/// Applies the migrations that haven't been applied to the archive yet, each in
+/// its own transaction.
+pub(crate) fn migrate(db: &Connection) -> Result<(), db::Error> {
+    let version = schema_version(db)?;
+
+    if version > SCHEMA_VERSION {
+        return Err(db::Error::Generic(format!(
+            "archive schema version {version} is newer than the supported version {SCHEMA_VERSION}"
+        )));
+    }
+
+    db.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER NOT NULL PRIMARY KEY,
+            applied_at TEXT NOT NULL
+        );
+        "#,
+    )?;
+
+    for (migration, version) in MIGRATIONS.iter().zip(1..).skip(version as usize) {
+        info!("Migrating archive to schema version {version}...");
+
+        db.execute_batch("BEGIN TRANSACTION")?;
+
+        let result = db.execute_batch(migration).and_then(|_| {
+            db.execute(
+                "INSERT INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+                (version, Utc::now().to_rfc3339()),
+            )
+        });
+
+        match result {
+            Ok(_) => db.execute_batch("COMMIT")?,
+            Err(e) => {
+                db.execute_batch("ROLLBACK")?;
+                return Err(e.into());
+            },
+        }
+    }
+
+    // Links can't be extracted in SQL, so those of archives built before are
+    // extracted once their schema is migrated.
+    if version > 0 && version < LINKS_VERSION {
+        links::extract(db)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    // An archive as built with schema version 1, from a fixture rather than
+    // from the first migration so that editing it is caught.
+    fn archive_v1() -> Connection {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archive-v1.sql");
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(&fs::read_to_string(path).unwrap()).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_migrate_empty_database() {
+        let db = Connection::open_in_memory().unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        migrate(&db).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+
+        // Migrating again is a no-op.
+        migrate(&db).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_v1_archive() {
+        let db = archive_v1();
+        assert_eq!(schema_version(&db).unwrap(), 1);
+
+        migrate(&db).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), SCHEMA_VERSION);
+
+        let versions = db
+            .prepare("SELECT version FROM schema_version ORDER BY version")
+            .unwrap()
+            .query_map([], |row| row.get::<_, u32>(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(versions, (1..=SCHEMA_VERSION).collect::<Vec<_>>());
+
+        let content: String =
+            db.query_row("SELECT content FROM messages", [], |row| row.get(0)).unwrap();
+        assert_eq!(content, "hello https://example.com");
+
+        // The links of archives built before they were indexed are extracted.
+        let domain: String =
+            db.query_row("SELECT domain FROM links", [], |row| row.get(0)).unwrap();
+        assert_eq!(domain, "example.com");
+    }
+
+    #[test]
+    fn test_refuse_newer_archive() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate(&db).unwrap();
+        db.execute("INSERT INTO schema_version (version, applied_at) VALUES (?1, '')", [
+            SCHEMA_VERSION + 1,
+        ])
+        .unwrap();
+
+        assert!(migrate(&db).is_err());
+    }
+}
@@ -24,10 +24,6 @@ CREATE TABLE IF NOT EXISTS messages (
     FOREIGN KEY(channel_id) REFERENCES channels(channel_id)
 );
 
--- Create messages/channel index.
-CREATE INDEX messages_channels
-ON messages(channel_id);
-
 -- Create full-text search table.
 CREATE VIRTUAL TABLE messages_fts
 USING FTS5(content, username, avatar, messages_rowid);
@@ -39,3 +35,9 @@ CREATE TABLE IF NOT EXISTS messages_pages (
     page INTEGER NOT NULL,
     FOREIGN KEY(messages_rowid) REFERENCES messages(rowid)
 );
+
+-- Create build metadata table.
+CREATE TABLE IF NOT EXISTS metadata (
+    key TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
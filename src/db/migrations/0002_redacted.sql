@@ -0,0 +1,2 @@
+-- Mark messages replaced by a placeholder by a redaction.
+ALTER TABLE messages ADD COLUMN redacted INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- Record the backups the archive was built from, and which one each message
+-- was read from.
+CREATE TABLE IF NOT EXISTS sources (
+    source_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL
+);
+
+ALTER TABLE messages ADD COLUMN source_id INTEGER REFERENCES sources(source_id);
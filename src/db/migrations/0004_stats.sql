@@ -0,0 +1,37 @@
+-- Aggregate the number of messages per channel, user and month, and per
+-- channel, month, weekday and hour, for the statistics pages. Redacted
+-- messages aren't counted.
+CREATE TABLE IF NOT EXISTS stats_messages (
+    channel_id INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    month TEXT NOT NULL,
+    messages INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, username, month)
+);
+
+CREATE TABLE IF NOT EXISTS stats_activity (
+    channel_id INTEGER NOT NULL,
+    month TEXT NOT NULL,
+    weekday INTEGER NOT NULL,
+    hour INTEGER NOT NULL,
+    messages INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, month, weekday, hour)
+);
+
+-- Archives built before are populated right away.
+INSERT INTO stats_messages (channel_id, username, month, messages)
+SELECT channel_id, username, strftime('%Y-%m', sent_at), COUNT(*)
+FROM messages
+WHERE NOT redacted
+GROUP BY 1, 2, 3;
+
+INSERT INTO stats_activity (channel_id, month, weekday, hour, messages)
+SELECT
+    channel_id,
+    strftime('%Y-%m', sent_at),
+    CAST(strftime('%w', sent_at) AS INTEGER),
+    CAST(strftime('%H', sent_at) AS INTEGER),
+    COUNT(*)
+FROM messages
+WHERE NOT redacted
+GROUP BY 1, 2, 3, 4;
@@ -0,0 +1,3 @@
+-- Index the messages of each user, for the user pages.
+CREATE INDEX IF NOT EXISTS messages_users
+ON messages(username, sent_at);
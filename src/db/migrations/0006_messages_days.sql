@@ -0,0 +1,19 @@
+-- Count the messages of each channel per day, in UTC, along with the first
+-- message of the day, for the calendar pages.
+CREATE TABLE IF NOT EXISTS messages_days (
+    channel_id INTEGER NOT NULL,
+    day TEXT NOT NULL,
+    messages INTEGER NOT NULL,
+    first_rowid INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, day)
+);
+
+-- Archives built before are populated right away. SQLite takes the bare
+-- `rowid` from the row with the earliest `sent_at`.
+INSERT INTO messages_days (channel_id, day, messages, first_rowid)
+SELECT channel_id, day, messages, first_rowid FROM (
+    SELECT channel_id, date(sent_at) AS day, COUNT(*) AS messages, rowid AS first_rowid,
+        MIN(sent_at)
+    FROM messages
+    GROUP BY channel_id, day
+);
@@ -0,0 +1,12 @@
+-- Index the URLs posted in messages, along with their domain. The table is
+-- filled by the build, as URLs can't be extracted in SQL.
+CREATE TABLE IF NOT EXISTS links (
+    messages_rowid INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    domain TEXT NOT NULL,
+    url TEXT NOT NULL,
+    FOREIGN KEY(messages_rowid) REFERENCES messages(rowid)
+);
+
+CREATE INDEX IF NOT EXISTS links_domains
+ON links(domain);
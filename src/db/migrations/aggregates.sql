@@ -0,0 +1,31 @@
+-- Recompute the aggregate tables of the statistics and calendar pages.
+DELETE FROM stats_messages;
+DELETE FROM stats_activity;
+DELETE FROM messages_days;
+
+INSERT INTO stats_messages (channel_id, username, month, messages)
+SELECT channel_id, username, strftime('%Y-%m', sent_at), COUNT(*)
+FROM messages
+WHERE NOT redacted
+GROUP BY 1, 2, 3;
+
+INSERT INTO stats_activity (channel_id, month, weekday, hour, messages)
+SELECT
+    channel_id,
+    strftime('%Y-%m', sent_at),
+    CAST(strftime('%w', sent_at) AS INTEGER),
+    CAST(strftime('%H', sent_at) AS INTEGER),
+    COUNT(*)
+FROM messages
+WHERE NOT redacted
+GROUP BY 1, 2, 3, 4;
+
+-- Redaction placeholders are shown in channels, so they are counted here.
+-- SQLite takes the bare `rowid` from the row with the earliest `sent_at`.
+INSERT INTO messages_days (channel_id, day, messages, first_rowid)
+SELECT channel_id, day, messages, first_rowid FROM (
+    SELECT channel_id, date(sent_at) AS day, COUNT(*) AS messages, rowid AS first_rowid,
+        MIN(sent_at)
+    FROM messages
+    GROUP BY channel_id, day
+);
@@ -0,0 +1,15 @@
+-- Create messages/channel index.
+CREATE INDEX messages_channels
+ON messages(channel_id);
+
+-- Create channel pages index.
+CREATE INDEX messages_pages_channels
+ON messages_pages(channel_id, page);
+
+-- Create messages/users index.
+CREATE INDEX messages_users
+ON messages(username, sent_at);
+
+-- Create links/domains index.
+CREATE INDEX links_domains
+ON links(domain);
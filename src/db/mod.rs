@@ -1,18 +1,38 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Days, NaiveDate, Utc};
 use itertools::Itertools;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
 use thiserror::Error;
-use tokio::fs;
+use tokio::{fs, task};
 
+use crate::anonymise::{self, AnonymiseConfig, Anonymiser};
+use crate::calendar::Day;
+use crate::index::{ArchiveSummary, RecentChannel, RECENT_CHANNELS, RECENT_DAYS};
+use crate::links::{Link, LinkPage, LinksQuery, LINKS_PER_PAGE, TOP_DOMAINS};
 use crate::search::{SearchQuery, SearchResult};
+use crate::stats::{ChannelStats, Count, Stats, StatsQuery, TOP_POSTERS, TOP_USERS};
+use crate::user::{UserChannel, UserMessage, UserSummary};
 use crate::{
-    Category, Channel, ChannelCategory, ChannelList, ChannelListEntry, Content, Message,
-    MessageContent, SQLITE_ARCHIVE_PATH,
+    Channel, ChannelCategory, ChannelList, ChannelListEntry, Message, MessageContent, MessageKey,
+    REDACTIONS_PATH, SQLITE_ARCHIVE_PATH,
 };
 
+mod fingerprint;
+mod import;
 mod init;
+mod links;
+mod migrate;
+mod redact;
+mod visibility;
+
+pub use migrate::SCHEMA_VERSION;
+pub use redact::Redaction;
+pub use visibility::ImportConfig;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -28,142 +48,252 @@ pub enum Error {
     LoadChannel(PathBuf, std::io::Error),
     #[error("Search query build error: {0}")]
     SearchQueryBuild(std::fmt::Error),
+    #[error("import task failed: {0}")]
+    Join(#[from] task::JoinError),
+    #[error(
+        "archive database {0:?} not found. Run `amardiscord` with the backup directory in \
+         read-write mode to build it"
+    )]
+    MissingArchive(PathBuf),
+    #[error("anonymising users: {0}")]
+    Anonymise(#[from] anonymise::Error),
     #[error("{0}")]
     Generic(String),
 }
 
+impl Error {
+    /// Whether the error was caused by a query that matched no rows.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Rusqlite(rusqlite::Error::QueryReturnedNoRows))
+    }
+}
+
 const PAGE_SIZE: u64 = 100;
 
-async fn load_categories(path: &Path) -> Result<Vec<Category>, Error> {
-    let path = path.join("categories");
+/// State of the archive database on disk.
+pub enum ArchiveStatus {
+    /// There is no archive.
+    Missing,
+    /// The archive exists but can't be served, e.g. because its build was
+    /// interrupted.
+    Invalid(String),
+    /// The archive was completely built from a backup with the given
+    /// fingerprint. Its schema may need to be migrated to [`SCHEMA_VERSION`].
+    Complete { fingerprint: String, schema_version: u32 },
+}
+
+/// Inspects the archive's completed-build marker.
+pub fn archive_status() -> Result<ArchiveStatus, Error> {
+    let path = Path::new(SQLITE_ARCHIVE_PATH);
 
     if !path.exists() {
-        return Err(Error::Generic(format!("{path:?} not found.")));
+        return Ok(ArchiveStatus::Missing);
     }
 
-    let mut category_indices = Vec::new();
-
-    // List all files in the `categories` directory, looking for files named
-    // `<number>.json`.
-    let mut entries = fs::read_dir(&path).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-
-        // Skip non-.json files.
-        if path.extension().and_then(|s| s.to_str()) != Some("json") {
-            break;
-        }
-
-        // Extract stems from filenames (e.g. `1.json` -> `1`).
-        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
-            break;
-        };
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
 
-        // Try parsing the stem into an integer and, if successful, save it.
-        if let Ok(category_index) = stem.parse::<i32>() {
-            category_indices.push(category_index);
-        }
+    let has_metadata: bool = db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_metadata {
+        return Ok(ArchiveStatus::Invalid("no build metadata".to_string()));
     }
 
-    // Category file names are ordered in the same way they are on a server.
-    // Sort them to replicate the server's structure.
-    category_indices.sort();
+    let get = |key: &str| {
+        db.query_row("SELECT value FROM metadata WHERE key = ?1", [key], |row| row.get(0))
+            .optional()
+    };
 
-    let mut categories = Vec::new();
+    let (Some(fingerprint), Some(_)) = (get("source_fingerprint")?, get("built_at")?) else {
+        return Ok(ArchiveStatus::Invalid("build was not completed".to_string()));
+    };
 
-    // Load category files in the correct order.
-    for i in category_indices {
-        let path = path.join(format!("{i}.json"));
-        let content = fs::read_to_string(path).await?;
-        let mut category: Category = serde_json::from_str(&content)?;
+    let schema_version = migrate::schema_version(&db)?;
+    if schema_version > SCHEMA_VERSION {
+        return Ok(ArchiveStatus::Invalid(format!(
+            "schema version {schema_version} is newer than {SCHEMA_VERSION}"
+        )));
+    }
 
-        for channel in &mut category.children {
-            if let Some(msgs) = channel.messages.as_mut() {
-                msgs.sort_unstable_by(|a, b| b.sent_at.cmp(&a.sent_at));
-            }
-        }
+    Ok(ArchiveStatus::Complete { fingerprint, schema_version })
+}
 
-        categories.push(category);
+/// Computes the fingerprint of the backups in `path`, to be compared with the
+/// one recorded in the archive.
+///
+/// Returns `None` if there is no backup directory in `path`.
+pub fn source_fingerprint(path: &Path, config: &ImportConfig) -> Result<Option<String>, Error> {
+    let backup_paths = import::find_backup_dirs(path)?;
+    if backup_paths.is_empty() {
+        return Ok(None);
     }
 
-    Ok(categories)
+    fingerprint::fingerprint(&backup_paths, config).map(Some)
 }
 
-async fn load_channels(path: &Path) -> Result<Vec<Channel>, Error> {
-    let path = path.join("other_channels");
-    let mut channels = Vec::new();
+/// Whether the archive can be written to, which migrating or rebuilding it
+/// requires. Archives on read-only mounts can't.
+pub fn archive_is_writable() -> bool {
+    let path = Path::new(SQLITE_ARCHIVE_PATH);
+    !path.exists() || std::fs::OpenOptions::new().append(true).open(path).is_ok()
+}
 
-    if !path.exists() {
-        return Ok(channels);
-    }
-
-    let mut entries = fs::read_dir(&path).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.extension().and_then(|s| s.to_str()) == Some("json") {
-            let content =
-                fs::read_to_string(&path).await.map_err(|e| Error::LoadChannel(path, e))?;
-            let mut channel: Channel = serde_json::from_str(&content)?;
-            if let Some(msgs) = channel.messages.as_mut() {
-                msgs.sort_by_key(|msg| msg.sent_at);
-            }
+/// Migrates the archive to the current [`SCHEMA_VERSION`].
+pub fn migrate() -> Result<(), Error> {
+    migrate::migrate(&Connection::open(SQLITE_ARCHIVE_PATH)?)
+}
 
-            channels.push(channel);
-        }
+/// Identifies a message of the archive from its id, for [`redact`].
+pub fn message_redaction(rowid: u64) -> Result<Redaction, Error> {
+    let path = Path::new(SQLITE_ARCHIVE_PATH);
+    if !path.exists() {
+        return Err(Error::MissingArchive(path.to_path_buf()));
     }
 
-    Ok(channels)
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    redact::for_message(&db, rowid)?
+        .ok_or_else(|| Error::Generic(format!("message {rowid} not found in the archive")))
 }
 
-pub async fn load_content(path: &Path) -> Result<Content, Error> {
-    let mut entries = fs::read_dir(path).await?;
+/// Adds a redaction to the list applied to every build, and applies it to the
+/// archive if it has been built.
+///
+/// If users are anonymised, they can be redacted by their name or pseudonym.
+///
+/// Returns the number of messages redacted.
+pub fn redact(redaction: Redaction, anonymise: &AnonymiseConfig) -> Result<u64, Error> {
+    let mut anonymiser = Anonymiser::new(anonymise)?;
+    redaction.pattern()?;
 
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.is_dir() {
-            return Ok(Content {
-                categories: load_categories(&path).await?,
-                channels: load_channels(&path).await?,
-            });
-        }
+    let list_path = Path::new(REDACTIONS_PATH);
+    let mut redactions = redact::load(list_path)?;
+    redactions.push(redaction.clone());
+    redact::save(list_path, redactions)?;
+
+    match archive_status()? {
+        ArchiveStatus::Complete { .. } => {
+            let db = Connection::open(SQLITE_ARCHIVE_PATH)?;
+            migrate::migrate(&db)?;
+            let redacted = redact::apply(&db, &[redaction], anonymiser.as_mut())?;
+            if redacted > 0 {
+                init::cache_pages(&db)?;
+                init::cache_aggregates(&db)?;
+            }
+            Ok(redacted)
+        },
+        // The redaction is applied when the archive is built.
+        ArchiveStatus::Missing | ArchiveStatus::Invalid(_) => Ok(0),
     }
+}
 
-    Ok(Default::default())
+/// Narrows down the messages of a channel.
+#[derive(Default, Clone, Debug)]
+pub struct MessageFilter {
+    /// Only the messages sent at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only the messages sent before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Only the messages of this user.
+    pub username: Option<String>,
 }
 
 pub struct Database(Pool<SqliteConnectionManager>);
 
+/// Utilisation of the database connection pool.
+pub struct PoolState {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
 impl Database {
-    pub fn new() -> Result<Self, Error> {
-        Ok(Self(
-            Pool::builder()
-                .max_size(32)
-                .build(SqliteConnectionManager::file(SQLITE_ARCHIVE_PATH))?,
-        ))
+    /// Opens the archive for serving.
+    ///
+    /// Connections are read-only. If `immutable` is set, SQLite additionally
+    /// assumes that nobody else modifies the file, which skips locking
+    /// entirely.
+    pub fn new(immutable: bool) -> Result<Self, Error> {
+        let path = Path::new(SQLITE_ARCHIVE_PATH);
+
+        if !path.exists() {
+            return Err(Error::MissingArchive(path.to_path_buf()));
+        }
+
+        let manager = if immutable {
+            SqliteConnectionManager::file(format!("file:{}?immutable=1", uri_path(path)))
+        } else {
+            SqliteConnectionManager::file(path)
+        };
+
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+
+        let manager = manager.with_flags(flags).with_init(|db| {
+            db.execute_batch(
+                r#"
+                PRAGMA query_only = ON;
+                PRAGMA cache_size = -65536;
+                PRAGMA mmap_size = 268435456;
+                "#,
+            )
+        });
+
+        Ok(Self(Pool::builder().max_size(32).build(manager)?))
+    }
+
+    pub fn pool_state(&self) -> PoolState {
+        let state = self.0.state();
+        PoolState {
+            max_size: self.0.max_size(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+        }
+    }
+
+    fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self(Pool::builder().max_size(32).build(SqliteConnectionManager::file(path))?))
     }
 
-    async fn initialize(&mut self, path: &Path) -> Result<(), Error> {
+    fn initialize(&self, path: &Path, config: &ImportConfig) -> Result<(), Error> {
         let db = self.0.get()?;
 
         // Initialize database
         init::initialize(&db)?;
 
-        // Load content
-        let content = load_content(path).await?;
-        let mut categories = content.categories;
-        if !content.channels.is_empty() {
-            categories
-                .push(Category { name: "Other channels".to_string(), children: content.channels });
-        }
+        // Fingerprint the backups before reading them, so that changes made
+        // during the build are detected afterwards.
+        let backup_paths = import::find_backup_dirs(path)?;
+        let fingerprint = if backup_paths.is_empty() {
+            String::new()
+        } else {
+            fingerprint::fingerprint(&backup_paths, config)?
+        };
 
-        // Insert categories
-        for category in categories {
-            init::insert_category(category, &db)?;
+        // Stream content from the backup directories, if there are any, merging
+        // them in the order of their names.
+        if !backup_paths.is_empty() {
+            let mut writer = init::Writer::new(&db, config)?;
+            for path in &backup_paths {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                writer.begin_source(&name)?;
+                import::import(path, config, &mut writer)?;
+            }
+            writer.finish()?;
         }
 
+        // Remove the redacted messages before they are indexed.
+        let redactions = redact::load(Path::new(REDACTIONS_PATH))?;
+        redact::apply(&db, &redactions, Anonymiser::new(&config.anonymise)?.as_mut())?;
+
         // Cache expensive queries.
         init::cache(&db)?;
 
+        // Mark the build as completed.
+        init::complete(&db, &fingerprint)?;
+
         Ok(())
     }
 
@@ -204,30 +334,223 @@ impl Database {
         )?)
     }
 
+    /// Retrieves the key of a message, which identifies it in the databases
+    /// kept next to the archive.
+    pub fn get_message_key(&self, message_rowid: u64) -> Result<MessageKey, Error> {
+        let mut keys = self.get_message_keys(&[message_rowid])?;
+        let (_, key) = keys.pop().ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        Ok(key)
+    }
+
+    /// Retrieves the keys of some messages, along with their ids, leaving out
+    /// the messages that don't exist.
+    pub fn get_message_keys(&self, rowids: &[u64]) -> Result<Vec<(u64, MessageKey)>, Error> {
+        if rowids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let db = self.0.get()?;
+
+        let keys = db
+            .prepare(&format!(
+                r#"
+                SELECT m.rowid, c.name, m.sent_at, m.username FROM messages AS m
+                JOIN channels AS c ON c.channel_id = m.channel_id
+                WHERE m.rowid IN ({})
+                "#,
+                placeholders(1, rowids.len())
+            ))?
+            .query_map(rusqlite::params_from_iter(rowids), |row| {
+                let key = MessageKey {
+                    channel: row.get(1)?,
+                    sent_at: row.get(2)?,
+                    username: row.get(3)?,
+                };
+                Ok((row.get(0)?, key))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(keys)
+    }
+
+    /// Finds the id of a message from its key, in the current build of the
+    /// archive. Of messages sharing a key, the first one imported is found.
+    pub fn find_message(&self, key: &MessageKey) -> Result<u64, Error> {
+        let db = self.0.get()?;
+
+        Ok(db.query_row(
+            r#"
+            SELECT m.rowid FROM messages AS m
+            JOIN channels AS c ON c.channel_id = m.channel_id
+            WHERE m.username = ?3 AND m.sent_at = ?2 AND c.name = ?1
+            ORDER BY m.rowid
+            LIMIT 1
+            "#,
+            (&key.channel, key.sent_at, &key.username),
+            |row| row.get(0),
+        )?)
+    }
+
     pub fn get_page(&self, channel_id: u64, page: u64) -> Result<Vec<Message>, Error> {
         let db = self.0.get()?;
 
         let mut stmt = db.prepare(
             r#"
-            SELECT content, username, avatar, sent_at, rowid FROM messages
-            WHERE channel_id = ?1
-            LIMIT ?2 OFFSET ?3
+            SELECT m.content, m.username, m.avatar, m.sent_at, m.rowid, m.redacted
+            FROM messages_pages AS p
+            JOIN messages AS m ON m.rowid = p.messages_rowid
+            WHERE p.channel_id = ?1 AND p.page = ?2
+            ORDER BY m.sent_at DESC, m.rowid DESC
             "#,
         )?;
 
-        let messages = stmt.query_map((channel_id, PAGE_SIZE, page * PAGE_SIZE), |row| {
+        let messages = stmt.query_map((channel_id, page), |row| {
             Ok(Message {
                 content: MessageContent(row.get(0)?),
                 username: row.get(1)?,
                 avatar: row.get(2)?,
                 sent_at: row.get(3)?,
                 rowid: row.get(4)?,
+                redacted: row.get(5)?,
             })
         })?;
 
         Ok(messages.collect::<rusqlite::Result<Vec<_>>>()?)
     }
 
+    /// Retrieves a message along with up to `size` messages sent before and
+    /// after it in its channel, oldest first. Returns the id and name of the
+    /// channel as well.
+    pub fn get_message_context(
+        &self,
+        message_rowid: u64,
+        size: usize,
+    ) -> Result<(u64, String, Vec<Message>), Error> {
+        let (channel_id, channel_name, page) = self.go_to_message(message_rowid)?;
+        let db = self.0.get()?;
+
+        // The messages around the first and last messages of a page are on the
+        // pages next to it.
+        let messages = db
+            .prepare(
+                r#"
+                SELECT m.content, m.username, m.avatar, m.sent_at, m.rowid, m.redacted
+                FROM messages_pages AS p
+                JOIN messages AS m ON m.rowid = p.messages_rowid
+                WHERE p.channel_id = ?1 AND p.page BETWEEN ?2 AND ?3
+                ORDER BY m.sent_at, m.rowid
+                "#,
+            )?
+            .query_map((channel_id, page.saturating_sub(1), page + 1), |row| {
+                Ok(Message {
+                    content: MessageContent(row.get(0)?),
+                    username: row.get(1)?,
+                    avatar: row.get(2)?,
+                    sent_at: row.get(3)?,
+                    rowid: row.get(4)?,
+                    redacted: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let index = messages.iter().position(|m| m.rowid == message_rowid).unwrap_or_default();
+        let context = messages
+            .into_iter()
+            .skip(index.saturating_sub(size))
+            .take(size + 1 + index.min(size))
+            .collect();
+
+        Ok((channel_id, channel_name, context))
+    }
+
+    /// Calls `f` with every message of a channel matching `filter`, oldest
+    /// first. Redacted messages are left out.
+    pub fn for_each_message(
+        &self,
+        channel_id: u64,
+        filter: &MessageFilter,
+        mut f: impl FnMut(Message) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let db = self.0.get()?;
+
+        let mut stmt = db.prepare(
+            r#"
+            SELECT content, username, avatar, sent_at, rowid FROM messages
+            WHERE channel_id = ?1 AND NOT redacted
+                AND (?2 IS NULL OR sent_at >= ?2)
+                AND (?3 IS NULL OR sent_at < ?3)
+                AND (?4 IS NULL OR username = ?4)
+            ORDER BY sent_at
+            "#,
+        )?;
+
+        let messages =
+            stmt.query_map((channel_id, filter.since, filter.until, &filter.username), |row| {
+                Ok(Message {
+                    content: MessageContent(row.get(0)?),
+                    username: row.get(1)?,
+                    avatar: row.get(2)?,
+                    sent_at: row.get(3)?,
+                    rowid: row.get(4)?,
+                    redacted: false,
+                })
+            })?;
+
+        for message in messages {
+            f(message?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of messages of a channel in each year with messages, in UTC.
+    pub fn get_channel_years(&self, channel_id: u64) -> Result<Vec<(i32, u64)>, Error> {
+        let db = self.0.get()?;
+
+        let mut stmt = db.prepare(
+            r#"
+            SELECT CAST(substr(day, 1, 4) AS INTEGER) AS year, SUM(messages) FROM messages_days
+            WHERE channel_id = ?1
+            GROUP BY year
+            ORDER BY year
+            "#,
+        )?;
+
+        let years = stmt.query_map([channel_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(years.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Days of a year with messages in a channel, in chronological order.
+    pub fn get_channel_days(&self, channel_id: u64, year: i32) -> Result<Vec<Day>, Error> {
+        let db = self.0.get()?;
+
+        let mut stmt = db.prepare(
+            r#"
+            SELECT day, messages, first_rowid FROM messages_days
+            WHERE channel_id = ?1 AND day LIKE ?2 || '-%'
+            ORDER BY day
+            "#,
+        )?;
+
+        let days = stmt.query_map((channel_id, year), |row| {
+            Ok(Day { date: row.get(0)?, messages: row.get(1)?, first_rowid: row.get(2)? })
+        })?;
+
+        Ok(days.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Number of pages of a channel.
+    pub fn get_page_count(&self, channel_id: u64) -> Result<u64, Error> {
+        let db = self.0.get()?;
+
+        Ok(db.query_row(
+            "SELECT COALESCE(MAX(page) + 1, 0) FROM messages_pages WHERE channel_id = ?1",
+            [channel_id],
+            |row| row.get(0),
+        )?)
+    }
+
     pub fn get_search(&self, search_query: SearchQuery) -> Result<Vec<SearchResult>, Error> {
         if search_query.is_empty() {
             return Ok(Vec::new());
@@ -244,6 +567,392 @@ impl Database {
         Ok(messages.collect::<rusqlite::Result<Vec<_>>>()?)
     }
 
+    /// Retrieves some messages as search results, e.g. those found by their
+    /// annotations. Redacted messages are left out.
+    pub fn get_search_results(&self, rowids: &[u64]) -> Result<Vec<SearchResult>, Error> {
+        if rowids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let db = self.0.get()?;
+
+        let mut stmt = db.prepare(&format!(
+            r#"
+            SELECT content, username, avatar, sent_at, channel_id, rowid
+            FROM messages
+            WHERE rowid IN ({}) AND NOT redacted
+            ORDER BY sent_at DESC
+            "#,
+            placeholders(1, rowids.len())
+        ))?;
+
+        let messages =
+            stmt.query_map(rusqlite::params_from_iter(rowids), SearchResult::from_row)?;
+
+        Ok(messages.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Computes the statistics of the messages matching `query` among those
+    /// of `channel_ids`.
+    pub fn get_stats(&self, query: &StatsQuery, channel_ids: &[u64]) -> Result<Stats, Error> {
+        let db = self.0.get()?;
+
+        // `?1` is the queried channel, `?2` the queried year, and the next
+        // parameters the channels the statistics are computed from.
+        let channels = placeholders(3, channel_ids.len());
+        let filter = format!(
+            "channel_id IN ({channels}) AND (?1 IS NULL OR channel_id = ?1) AND (?2 IS NULL OR \
+             month LIKE ?2 || '-%')"
+        );
+
+        let params = |year: Option<i32>| {
+            let mut params = vec![Value::from(query.channel.map(|id| id as i64)), year.into()];
+            params.extend(channel_ids.iter().map(|&id| Value::Integer(id as i64)));
+            rusqlite::params_from_iter(params)
+        };
+
+        let mut top_posters = HashMap::<u64, Vec<Count>>::new();
+        let mut stmt = db.prepare(&format!(
+            r#"
+            SELECT channel_id, username, messages FROM (
+                SELECT channel_id, username, SUM(messages) AS messages, ROW_NUMBER() OVER (
+                    PARTITION BY channel_id ORDER BY SUM(messages) DESC, username
+                ) AS rank
+                FROM stats_messages
+                WHERE {filter}
+                GROUP BY channel_id, username
+            )
+            WHERE rank <= {TOP_POSTERS}
+            ORDER BY channel_id, rank
+            "#
+        ))?;
+        let mut rows = stmt.query(params(query.year))?;
+        while let Some(row) = rows.next()? {
+            top_posters
+                .entry(row.get(0)?)
+                .or_default()
+                .push(Count { name: row.get(1)?, messages: row.get(2)? });
+        }
+
+        let channels = db
+            .prepare(&format!(
+                r#"
+                SELECT channel_id, channels.name, SUM(messages) FROM stats_messages
+                JOIN channels USING (channel_id)
+                WHERE {filter}
+                GROUP BY channel_id
+                ORDER BY 3 DESC, channel_id
+                "#
+            ))?
+            .query_map(params(query.year), |row| {
+                let channel_id = row.get(0)?;
+                Ok(ChannelStats {
+                    channel_id,
+                    name: row.get(1)?,
+                    messages: row.get(2)?,
+                    top_posters: top_posters.remove(&channel_id).unwrap_or_default(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let count = |row: &rusqlite::Row| Ok(Count { name: row.get(0)?, messages: row.get(1)? });
+        let users = db
+            .prepare(&format!(
+                r#"
+                SELECT username, SUM(messages) FROM stats_messages
+                WHERE {filter}
+                GROUP BY username
+                ORDER BY 2 DESC, username
+                LIMIT {TOP_USERS}
+                "#
+            ))?
+            .query_map(params(query.year), count)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let months = db
+            .prepare(&format!(
+                r#"
+                SELECT month, SUM(messages) FROM stats_messages
+                WHERE {filter}
+                GROUP BY month
+                ORDER BY month
+                "#
+            ))?
+            .query_map(params(query.year), count)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut activity = [[0; 24]; 7];
+        let mut stmt = db.prepare(&format!(
+            r#"
+            SELECT weekday, hour, SUM(messages) FROM stats_activity
+            WHERE {filter}
+            GROUP BY weekday, hour
+            "#
+        ))?;
+        let mut rows = stmt.query(params(query.year))?;
+        while let Some(row) = rows.next()? {
+            let (weekday, hour): (usize, usize) = (row.get(0)?, row.get(1)?);
+            if let Some(messages) = activity.get_mut(weekday).and_then(|hours| hours.get_mut(hour))
+            {
+                *messages = row.get(2)?;
+            }
+        }
+
+        // The years are listed regardless of the queried year.
+        let years = db
+            .prepare(&format!(
+                r#"
+                SELECT DISTINCT CAST(substr(month, 1, 4) AS INTEGER) FROM stats_messages
+                WHERE {filter}
+                ORDER BY 1
+                "#
+            ))?
+            .query_map(params(None), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Stats {
+            messages: channels.iter().map(|channel| channel.messages).sum(),
+            years,
+            channels,
+            users,
+            months,
+            activity,
+        })
+    }
+
+    /// Summarises the messages of a user among those of `channel_ids`.
+    ///
+    /// Returns `None` if the user has no message in these channels.
+    pub fn get_user(
+        &self,
+        username: &str,
+        channel_ids: &[u64],
+    ) -> Result<Option<UserSummary>, Error> {
+        let db = self.0.get()?;
+
+        let mut params = vec![Value::from(username.to_string())];
+        params.extend(channel_ids.iter().map(|&id| Value::Integer(id as i64)));
+
+        let rows = db
+            .prepare(&format!(
+                r#"
+                SELECT channel_id, channels.name, COUNT(*), MIN(sent_at), MAX(sent_at)
+                FROM messages
+                JOIN channels USING (channel_id)
+                WHERE username = ?1 AND channel_id IN ({})
+                GROUP BY channel_id
+                ORDER BY 3 DESC, channel_id
+                "#,
+                placeholders(2, channel_ids.len())
+            ))?
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let channel = UserChannel {
+                    channel_id: row.get(0)?,
+                    name: row.get(1)?,
+                    messages: row.get(2)?,
+                };
+                Ok((channel, row.get::<_, DateTime<Utc>>(3)?, row.get::<_, DateTime<Utc>>(4)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let first_sent_at = rows.iter().map(|(_, first, _)| *first).min();
+        let last_sent_at = rows.iter().map(|(_, _, last)| *last).max();
+        let (Some(first_sent_at), Some(last_sent_at)) = (first_sent_at, last_sent_at) else {
+            return Ok(None);
+        };
+
+        let channels = rows.into_iter().map(|(channel, ..)| channel).collect::<Vec<_>>();
+        let messages = channels.iter().map(|channel| channel.messages).sum::<u64>();
+
+        Ok(Some(UserSummary {
+            username: username.to_string(),
+            messages,
+            first_sent_at,
+            last_sent_at,
+            channels,
+            pages: messages.div_ceil(PAGE_SIZE),
+        }))
+    }
+
+    /// Lists a page of the messages of a user among those of `channel_ids`,
+    /// the latest first.
+    pub fn get_user_page(
+        &self,
+        username: &str,
+        page: u64,
+        channel_ids: &[u64],
+    ) -> Result<Vec<UserMessage>, Error> {
+        let db = self.0.get()?;
+
+        let mut params = vec![
+            Value::from(username.to_string()),
+            Value::Integer(PAGE_SIZE as i64),
+            Value::Integer(page.saturating_mul(PAGE_SIZE).min(i64::MAX as u64) as i64),
+        ];
+        params.extend(channel_ids.iter().map(|&id| Value::Integer(id as i64)));
+
+        let messages = db
+            .prepare(&format!(
+                r#"
+                SELECT m.content, m.username, m.avatar, m.sent_at, m.rowid, m.channel_id, c.name
+                FROM messages AS m
+                JOIN channels AS c ON c.channel_id = m.channel_id
+                WHERE m.username = ?1 AND m.channel_id IN ({})
+                ORDER BY m.sent_at DESC, m.rowid DESC
+                LIMIT ?2 OFFSET ?3
+                "#,
+                placeholders(4, channel_ids.len())
+            ))?
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                Ok(UserMessage {
+                    channel_id: row.get(5)?,
+                    channel_name: row.get(6)?,
+                    message: Message {
+                        content: MessageContent(row.get(0)?),
+                        username: row.get(1)?,
+                        avatar: row.get(2)?,
+                        sent_at: row.get(3)?,
+                        rowid: row.get(4)?,
+                        redacted: false,
+                    },
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(messages)
+    }
+
+    /// Summarises the messages of `channel_ids`, from the number of messages
+    /// sent each day.
+    pub fn get_archive_summary(&self, channel_ids: &[u64]) -> Result<ArchiveSummary, Error> {
+        let db = self.0.get()?;
+
+        // Redaction placeholders are only counted in the days, as they are on
+        // the calendar, and left out of the total, as they are on `/stats`.
+        let channels = placeholders(1, channel_ids.len());
+        let (first_day, last_day, messages) = db.query_row(
+            &format!(
+                r#"
+                SELECT MIN(day), MAX(day), (
+                    SELECT COALESCE(SUM(messages), 0) FROM stats_messages
+                    WHERE channel_id IN ({channels})
+                )
+                FROM messages_days
+                WHERE channel_id IN ({channels})
+                "#
+            ),
+            rusqlite::params_from_iter(channel_ids),
+            |row| {
+                let first_day: Option<NaiveDate> = row.get(0)?;
+                let last_day: Option<NaiveDate> = row.get(1)?;
+                Ok((first_day, last_day, row.get(2)?))
+            },
+        )?;
+
+        let since = last_day.map(|day| (day - Days::new(RECENT_DAYS)).to_string());
+        let mut params = vec![Value::Integer(RECENT_CHANNELS as i64), Value::from(since)];
+        params.extend(channel_ids.iter().map(|&id| Value::Integer(id as i64)));
+
+        let recent_channels = db
+            .prepare(&format!(
+                r#"
+                SELECT d.channel_id, c.name, SUM(d.messages)
+                FROM messages_days AS d
+                JOIN channels AS c ON c.channel_id = d.channel_id
+                WHERE d.day > ?2 AND d.channel_id IN ({})
+                GROUP BY d.channel_id
+                ORDER BY 3 DESC, d.channel_id
+                LIMIT ?1
+                "#,
+                placeholders(3, channel_ids.len())
+            ))?
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let (channel_id, name, messages) = (row.get(0)?, row.get(1)?, row.get(2)?);
+                Ok(RecentChannel { channel_id, name, messages })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(ArchiveSummary {
+            messages,
+            channels: channel_ids.len() as u64,
+            first_day,
+            last_day,
+            recent_channels,
+        })
+    }
+
+    /// Lists a page of the links posted in `channel_ids` matching `query`,
+    /// along with the domains linked to the most.
+    pub fn get_links(&self, query: &LinksQuery, channel_ids: &[u64]) -> Result<LinkPage, Error> {
+        let db = self.0.get()?;
+
+        let domain = query.domain.as_ref().map(|domain| {
+            let domain = domain.trim().to_lowercase();
+            domain.strip_prefix("www.").map(str::to_string).unwrap_or(domain)
+        });
+
+        // `?1` is the queried channel, `?2` the queried domain, `?3` and `?4`
+        // the range of links, and the next parameters the channels the links
+        // are listed from.
+        let mut params = vec![
+            Value::from(query.channel.map(|id| id as i64)),
+            Value::from(domain),
+            Value::Integer(LINKS_PER_PAGE as i64 + 1),
+            Value::Integer(query.page.saturating_mul(LINKS_PER_PAGE).min(i64::MAX as u64) as i64),
+        ];
+        params.extend(channel_ids.iter().map(|&id| Value::Integer(id as i64)));
+
+        let channels = format!(
+            "l.channel_id IN ({}) AND (?1 IS NULL OR l.channel_id = ?1)",
+            placeholders(5, channel_ids.len())
+        );
+
+        let mut links = db
+            .prepare(&format!(
+                r#"
+                SELECT l.url, l.domain, l.channel_id, c.name, l.messages_rowid, m.username,
+                    m.sent_at
+                FROM links AS l
+                JOIN messages AS m ON m.rowid = l.messages_rowid
+                JOIN channels AS c ON c.channel_id = l.channel_id
+                WHERE {channels} AND (?2 IS NULL OR l.domain = ?2 OR l.domain LIKE '%.' || ?2)
+                ORDER BY m.sent_at DESC, l.messages_rowid DESC, l.rowid
+                LIMIT ?3 OFFSET ?4
+                "#
+            ))?
+            .query_map(rusqlite::params_from_iter(params.clone()), |row| {
+                Ok(Link {
+                    url: row.get(0)?,
+                    domain: row.get(1)?,
+                    channel_id: row.get(2)?,
+                    channel_name: row.get(3)?,
+                    message_rowid: row.get(4)?,
+                    username: row.get(5)?,
+                    sent_at: row.get(6)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        // One more link than a page holds is retrieved to know whether there
+        // are more.
+        let more = links.len() as u64 > LINKS_PER_PAGE;
+        links.truncate(LINKS_PER_PAGE as usize);
+
+        let domains = db
+            .prepare(&format!(
+                r#"
+                SELECT l.domain, COUNT(*) FROM links AS l
+                WHERE {channels}
+                GROUP BY l.domain
+                ORDER BY 2 DESC, l.domain
+                LIMIT {TOP_DOMAINS}
+                "#
+            ))?
+            .query_map(rusqlite::params_from_iter(params), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(LinkPage { links, more, domains })
+    }
+
     pub fn get_channel_list(&self) -> Result<ChannelList, Error> {
         let db = self.0.get()?;
 
@@ -258,6 +967,7 @@ impl Database {
                 categories
                 JOIN channels
                 ON channels.category_id = categories.category_id
+            ORDER BY categories.category_id, channels.channel_id
             "#,
         )?;
 
@@ -285,12 +995,284 @@ impl Database {
     }
 }
 
-pub async fn build(path: Option<PathBuf>) -> Result<(), Error> {
+// Lists `count` numbered parameters, starting from `?{start}`.
+fn placeholders(start: usize, count: usize) -> String {
+    (start..start + count).map(|i| format!("?{i}")).join(", ")
+}
+
+// Escapes the characters that have a special meaning in SQLite URI filenames.
+fn uri_path(path: &Path) -> String {
+    path.to_string_lossy().replace('%', "%25").replace('?', "%3f").replace('#', "%23")
+}
+
+/// Builds the archive from the backup in `path`, importing the channels that
+/// `config` lets through.
+///
+/// The archive is built in a temporary file which replaces the previous
+/// archive only once the build has completed, so that an interrupted build
+/// never leaves a truncated archive behind.
+pub async fn build(path: Option<PathBuf>, config: ImportConfig) -> Result<(), Error> {
     let sqlite_path = Path::new(SQLITE_ARCHIVE_PATH);
+    let build_path = sqlite_path.with_extension("sqlite.tmp");
+
+    // Remove the leftovers of an interrupted build.
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let mut path = build_path.clone().into_os_string();
+        path.push(suffix);
+
+        if Path::new(&path).exists() {
+            fs::remove_file(path).await?;
+        }
+    }
+
+    let path = path.unwrap_or_else(|| PathBuf::from("./data"));
+    let db = Database::open(&build_path)?;
+
+    // The database is dropped at the end of the task, closing all connections
+    // before the file is moved.
+    task::spawn_blocking(move || db.initialize(&path, &config)).await??;
+
+    fs::rename(&build_path, sqlite_path).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive() -> Database {
+        let manager = SqliteConnectionManager::memory();
+        let db = Database(Pool::builder().max_size(1).build(manager).unwrap());
+        let conn = db.0.get().unwrap();
+        migrate::migrate(&conn).unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO categories (category_id, name) VALUES (1, 'Category');
+            INSERT INTO channels (channel_id, channel_type, name, category_id)
+            VALUES (1, 0, 'general', 1), (2, 0, 'routing', 1), (3, 0, 'secret', 1);
+            INSERT INTO messages (content, username, avatar, sent_at, channel_id, redacted) VALUES
+                ('hi https://www.youtube.com/watch?v=1', 'alice', '', '2019-01-06 10:00:00+00:00', 1, 0),
+                ('yo', 'bob', '', '2019-01-06 10:30:00+00:00', 1, 0),
+                ('route', 'bob', '', '2019-02-01 23:00:00+00:00', 2, 0),
+                ('skip', 'bob', '', '2019-03-01 23:00:00+00:00', 2, 0),
+                ('', '', '', '2019-03-02 23:00:00+00:00', 2, 1),
+                ('new https://m.youtube.com/a and https://github.com/b', 'alice', '', '2020-05-01 00:00:00+00:00', 2, 0),
+                ('psst https://example.com', 'carol', '', '2019-01-01 00:00:00+00:00', 3, 0);
+            "#,
+        )
+        .unwrap();
+        conn.execute_batch(
+            r#"
+            INSERT INTO messages_fts (content, username, avatar, messages_rowid)
+            SELECT content, username, avatar, rowid FROM messages;
+            "#,
+        )
+        .unwrap();
+        init::cache_aggregates(&conn).unwrap();
+        links::extract(&conn).unwrap();
+        drop(conn);
+        db
+    }
+
+    #[test]
+    fn test_get_search() {
+        let db = archive();
+        let search = |query| {
+            let query = serde_json::from_value(query).unwrap();
+            db.get_search(query).unwrap().iter().map(|r| r.message_rowid).collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(serde_json::json!({ "content": "route" })), [3]);
+        // Searches by a user find all of their messages, the latest first.
+        assert_eq!(search(serde_json::json!({ "username": "bob", "content": "" })), [4, 3, 2]);
+        // Along with content, they only find the messages of the user that
+        // match it.
+        assert_eq!(search(serde_json::json!({ "username": "bob", "content": "route" })), [3]);
+        assert_eq!(search(serde_json::json!({ "username": "alice", "content": "youtube" })), [
+            6, 1
+        ]);
+        assert!(search(serde_json::json!({ "username": "carol", "content": "route" })).is_empty());
+        // User names are matched whole.
+        assert!(search(serde_json::json!({ "username": "bo", "content": "" })).is_empty());
+    }
+
+    #[test]
+    fn test_get_stats() {
+        let db = archive();
+
+        let stats = db.get_stats(&StatsQuery::default(), &[1, 2]).unwrap();
+        assert_eq!(stats.messages, 5);
+        assert_eq!(stats.years, [2019, 2020]);
+        assert_eq!(
+            stats.channels.iter().map(|c| (c.name.as_str(), c.messages)).collect::<Vec<_>>(),
+            [("routing", 3), ("general", 2)]
+        );
+        assert_eq!(stats.users, [Count { name: "bob".to_string(), messages: 3 }, Count {
+            name: "alice".to_string(),
+            messages: 2
+        },]);
+        assert_eq!(stats.months.len(), 4);
+        // 2019-01-06 was a Sunday.
+        assert_eq!(stats.activity[0][10], 2);
+        assert_eq!(stats.activity[5][23], 2);
+
+        let query = StatsQuery { channel: Some(2), year: Some(2019) };
+        let stats = db.get_stats(&query, &[1, 2]).unwrap();
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.years, [2019, 2020]);
+        assert_eq!(stats.channels[0].top_posters, [Count { name: "bob".to_string(), messages: 2 }]);
+        assert_eq!(stats.months.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), [
+            "2019-02", "2019-03"
+        ]);
+    }
+
+    #[test]
+    fn test_get_user() {
+        let db = archive();
+
+        let summary = db.get_user("bob", &[1, 2]).unwrap().unwrap();
+        assert_eq!(summary.messages, 3);
+        assert_eq!(summary.first_sent_at.to_rfc3339(), "2019-01-06T10:30:00+00:00");
+        assert_eq!(summary.last_sent_at.to_rfc3339(), "2019-03-01T23:00:00+00:00");
+        assert_eq!(
+            summary.channels.iter().map(|c| (c.name.as_str(), c.messages)).collect::<Vec<_>>(),
+            [("routing", 2), ("general", 1)]
+        );
+        assert_eq!(summary.pages, 1);
+
+        let messages = db.get_user_page("bob", 0, &[1, 2]).unwrap();
+        assert_eq!(messages.iter().map(|m| m.message.content.as_ref()).collect::<Vec<_>>(), [
+            "skip", "route", "yo"
+        ]);
+        assert!(db.get_user_page("bob", 1, &[1, 2]).unwrap().is_empty());
 
-    if sqlite_path.exists() {
-        fs::remove_file(sqlite_path).await?;
+        // Users without visible messages are unknown.
+        assert!(db.get_user("carol", &[1, 2]).unwrap().is_none());
+        assert!(db.get_user("carol", &[3]).unwrap().is_some());
     }
 
-    Database::new()?.initialize(path.as_deref().unwrap_or_else(|| Path::new("./data"))).await
+    #[test]
+    fn test_get_channel_days() {
+        let db = archive();
+
+        assert_eq!(db.get_channel_years(2).unwrap(), [(2019, 3), (2020, 1)]);
+
+        let days = db.get_channel_days(2, 2019).unwrap();
+        assert_eq!(
+            days.iter().map(|day| (day.date.to_string(), day.messages)).collect::<Vec<_>>(),
+            [
+                ("2019-02-01".to_string(), 1),
+                ("2019-03-01".to_string(), 1),
+                ("2019-03-02".to_string(), 1)
+            ]
+        );
+        assert_eq!(days[0].first_rowid, 3);
+        assert!(db.get_channel_days(2, 2018).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_archive_summary() {
+        let db = archive();
+
+        let summary = db.get_archive_summary(&[1, 2]).unwrap();
+        assert_eq!((summary.messages, summary.channels), (5, 2));
+        // The redaction placeholder is left out, as on the statistics page.
+        let stats = db.get_stats(&StatsQuery::default(), &[1, 2]).unwrap();
+        assert_eq!(summary.messages, stats.messages);
+        assert_eq!(summary.first_day.unwrap().to_string(), "2019-01-06");
+        assert_eq!(summary.last_day.unwrap().to_string(), "2020-05-01");
+        assert_eq!(
+            summary
+                .recent_channels
+                .iter()
+                .map(|c| (c.name.as_str(), c.messages))
+                .collect::<Vec<_>>(),
+            [("routing", 1)]
+        );
+
+        let summary = db.get_archive_summary(&[1]).unwrap();
+        assert_eq!(summary.recent_channels[0].messages, 2);
+
+        let summary = db.get_archive_summary(&[]).unwrap();
+        assert_eq!(summary.messages, 0);
+        assert!(summary.last_day.is_none() && summary.recent_channels.is_empty());
+    }
+
+    #[test]
+    fn test_get_links() {
+        let db = archive();
+
+        let page = db.get_links(&LinksQuery::default(), &[1, 2]).unwrap();
+        assert_eq!(
+            page.links.iter().map(|l| (l.url.as_str(), l.message_rowid)).collect::<Vec<_>>(),
+            [
+                ("https://m.youtube.com/a", 6),
+                ("https://github.com/b", 6),
+                ("https://www.youtube.com/watch?v=1", 1)
+            ]
+        );
+        assert!(!page.more);
+        assert_eq!(page.domains, [
+            ("github.com".to_string(), 1),
+            ("m.youtube.com".to_string(), 1),
+            ("youtube.com".to_string(), 1)
+        ]);
+
+        // Domains match their subdomains.
+        let query =
+            LinksQuery { domain: Some("WWW.YouTube.com".to_string()), ..Default::default() };
+        let page = db.get_links(&query, &[1, 2]).unwrap();
+        assert_eq!(page.links.iter().map(|l| l.message_rowid).collect::<Vec<_>>(), [6, 1]);
+
+        let query = LinksQuery { channel: Some(1), ..Default::default() };
+        let page = db.get_links(&query, &[1, 2]).unwrap();
+        assert_eq!(page.links.len(), 1);
+        assert_eq!(page.domains, [("youtube.com".to_string(), 1)]);
+
+        // Links of hidden channels aren't listed.
+        let page = db.get_links(&LinksQuery::default(), &[3]).unwrap();
+        assert_eq!(page.links.iter().map(|l| l.domain.as_str()).collect::<Vec<_>>(), [
+            "example.com"
+        ]);
+    }
+
+    #[test]
+    fn test_get_message_context() {
+        let db = archive();
+        db.0.get()
+            .unwrap()
+            .execute_batch(
+                r#"
+                INSERT INTO messages_pages (page, messages_rowid, channel_id)
+                VALUES (1, 3, 2), (1, 4, 2), (0, 5, 2), (0, 6, 2);
+                "#,
+            )
+            .unwrap();
+
+        let rowids = |rowid, size| {
+            let (channel_id, _, messages) = db.get_message_context(rowid, size).unwrap();
+            assert_eq!(channel_id, 2);
+            messages.iter().map(|m| m.rowid).collect::<Vec<_>>()
+        };
+        assert_eq!(rowids(4, 1), [3, 4, 5]);
+        assert_eq!(rowids(3, 2), [3, 4, 5]);
+        assert_eq!(rowids(6, 2), [4, 5, 6]);
+
+        assert!(db.get_message_context(1, 2).unwrap_err().is_not_found());
+    }
+
+    #[test]
+    fn test_message_keys() {
+        let db = archive();
+
+        let key = db.get_message_key(3).unwrap();
+        assert_eq!((key.channel.as_str(), key.username.as_str()), ("routing", "bob"));
+        assert_eq!(db.find_message(&key).unwrap(), 3);
+        assert_eq!(db.get_message_keys(&[2, 3, 100]).unwrap().len(), 2);
+
+        let key = MessageKey { channel: "general".to_string(), ..key };
+        assert!(db.find_message(&key).unwrap_err().is_not_found());
+        assert!(db.get_message_key(100).unwrap_err().is_not_found());
+    }
 }
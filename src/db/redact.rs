@@ -0,0 +1,352 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::anonymise::Anonymiser;
+use crate::db::Error;
+use crate::MessageContent;
+
+/// A rule removing messages from the archive, e.g. at the request of their
+/// author.
+///
+/// A message is redacted if it matches every field that is set.
+///
+/// Fields are matched against the archive as it is served, and the user also
+/// against the name of anonymised users.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Redaction {
+    /// Name of the author of the messages.
+    pub user: Option<String>,
+    /// Name of the channel of the messages.
+    pub channel: Option<String>,
+    /// Time at which the message was sent. Along with the channel and the
+    /// user, it identifies a single message across builds, unlike its id.
+    pub sent_at: Option<DateTime<Utc>>,
+    /// Regular expression matched against the text of the messages.
+    pub pattern: Option<String>,
+    /// Replace the messages with a "Message removed" placeholder instead of
+    /// removing them altogether.
+    #[serde(default)]
+    pub placeholder: bool,
+    /// Why the messages were redacted, for future reference.
+    pub reason: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl Redaction {
+    /// Checks that the redaction targets some messages rather than the whole
+    /// archive, and compiles its pattern.
+    pub(crate) fn pattern(&self) -> Result<Option<Regex>, Error> {
+        if self.user.is_none()
+            && self.channel.is_none()
+            && self.sent_at.is_none()
+            && self.pattern.is_none()
+        {
+            return Err(Error::Generic("a redaction must target some messages".to_string()));
+        }
+
+        self.pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| Error::Generic(format!("invalid redaction pattern: {e}")))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RedactionList {
+    redactions: Vec<Redaction>,
+}
+
+/// Loads the redaction list, which is empty if the file doesn't exist.
+pub(crate) fn load(path: &Path) -> Result<Vec<Redaction>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let list: RedactionList = toml::from_str(&content)
+        .map_err(|e| Error::Generic(format!("parsing redactions from {path:?}: {e}")))?;
+
+    Ok(list.redactions)
+}
+
+pub(crate) fn save(path: &Path, redactions: Vec<Redaction>) -> Result<(), Error> {
+    let content = toml::to_string(&RedactionList { redactions })
+        .map_err(|e| Error::Generic(format!("serializing redactions: {e}")))?;
+
+    // Write the list to a temporary file first, so that it's never truncated.
+    let tmp_path = path.with_extension("toml.tmp");
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Builds the redaction of a single message from its id.
+pub(crate) fn for_message(db: &Connection, rowid: u64) -> Result<Option<Redaction>, Error> {
+    Ok(db
+        .query_row(
+            r#"
+            SELECT c.name, m.sent_at, m.username FROM messages AS m
+            JOIN channels AS c ON c.channel_id = m.channel_id
+            WHERE m.rowid = ?1
+            "#,
+            [rowid],
+            |row| {
+                Ok(Redaction {
+                    user: Some(row.get(2)?),
+                    channel: Some(row.get(0)?),
+                    sent_at: Some(row.get(1)?),
+                    pattern: None,
+                    placeholder: false,
+                    reason: None,
+                    added_at: Utc::now(),
+                })
+            },
+        )
+        .optional()?)
+}
+
+/// Applies redactions to the archive, removing the matching messages from the
+/// messages, FTS and page tables, or replacing them with placeholders.
+///
+/// Returns the number of messages redacted.
+pub(crate) fn apply(
+    db: &Connection,
+    redactions: &[Redaction],
+    anonymiser: Option<&mut Anonymiser>,
+) -> Result<u64, Error> {
+    if redactions.is_empty() {
+        return Ok(0);
+    }
+
+    db.execute_batch(
+        r#"
+        BEGIN TRANSACTION;
+        CREATE TEMP TABLE redacted (rowid INTEGER NOT NULL PRIMARY KEY, placeholder INTEGER NOT NULL);
+        "#,
+    )?;
+
+    let result = find_redacted(db, redactions, anonymiser).and_then(|redacted| {
+        // Overwrite the content of deleted rows, so that it can't be recovered
+        // from the archive file.
+        db.execute_batch(
+            r#"
+            PRAGMA secure_delete = ON;
+            DELETE FROM messages_fts WHERE messages_rowid IN (SELECT rowid FROM redacted);
+            DELETE FROM links WHERE messages_rowid IN (SELECT rowid FROM redacted);
+            DELETE FROM messages_pages
+            WHERE messages_rowid IN (SELECT rowid FROM redacted WHERE NOT placeholder);
+            DELETE FROM messages WHERE rowid IN (SELECT rowid FROM redacted WHERE NOT placeholder);
+            UPDATE messages SET content = '', username = '', avatar = '', redacted = 1
+            WHERE rowid IN (SELECT rowid FROM redacted WHERE placeholder);
+            PRAGMA secure_delete = OFF;
+            "#,
+        )?;
+
+        Ok(redacted)
+    });
+
+    match result {
+        Ok(redacted) => {
+            db.execute_batch("DROP TABLE redacted; COMMIT;")?;
+
+            // Deleted FTS entries linger in the index until its segments are
+            // merged.
+            let indexed = db
+                .query_row("SELECT 1 FROM messages_fts LIMIT 1", [], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if redacted > 0 && indexed {
+                db.execute("INSERT INTO messages_fts (messages_fts) VALUES ('optimize')", [])?;
+            }
+
+            info!("Redacted {redacted} messages.");
+            Ok(redacted)
+        },
+        Err(e) => {
+            db.execute_batch("ROLLBACK")?;
+            Err(e)
+        },
+    }
+}
+
+// Fills the `redacted` table with the messages matching the redactions.
+fn find_redacted(
+    db: &Connection,
+    redactions: &[Redaction],
+    mut anonymiser: Option<&mut Anonymiser>,
+) -> Result<u64, Error> {
+    let mut select = db.prepare(
+        r#"
+        SELECT m.rowid, m.content FROM messages AS m
+        JOIN channels AS c ON c.channel_id = m.channel_id
+        WHERE NOT m.redacted
+            AND (?1 IS NULL OR m.username IN (?1, ?4))
+            AND (?2 IS NULL OR c.name = ?2)
+            AND (?3 IS NULL OR m.sent_at = ?3)
+        "#,
+    )?;
+    // Removing a message takes precedence over leaving a placeholder.
+    let mut insert = db.prepare(
+        r#"
+        INSERT INTO redacted (rowid, placeholder) VALUES (?1, ?2)
+        ON CONFLICT (rowid) DO UPDATE SET placeholder = placeholder AND excluded.placeholder
+        "#,
+    )?;
+
+    for redaction in redactions {
+        let pattern = redaction.pattern()?;
+        let pseudonym = redaction
+            .user
+            .as_deref()
+            .zip(anonymiser.as_deref_mut())
+            .map(|(user, anonymiser)| anonymiser.username(user));
+
+        let mut rows =
+            select.query((&redaction.user, &redaction.channel, &redaction.sent_at, pseudonym))?;
+        while let Some(row) = rows.next()? {
+            let rowid: u64 = row.get(0)?;
+            let content = MessageContent(row.get(1)?);
+
+            if pattern.as_ref().is_none_or(|pattern| pattern.is_match(&content.plain_text())) {
+                insert.execute((rowid, redaction.placeholder))?;
+            }
+        }
+    }
+
+    Ok(db.query_row("SELECT COUNT(*) FROM redacted", [], |row| row.get(0))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate;
+
+    fn archive() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        migrate::migrate(&db).unwrap();
+        db.execute_batch(
+            r#"
+            INSERT INTO categories (category_id, name) VALUES (1, 'Category');
+            INSERT INTO channels (channel_id, channel_type, name, category_id)
+            VALUES (1, 0, 'general', 1), (2, 0, 'memes', 1);
+            INSERT INTO messages (rowid, content, username, avatar, sent_at, channel_id) VALUES
+                (1, 'hello', 'alice', '', '2020-01-01 00:00:00+00:00', 1),
+                (2, 'my address is 1 Main St', 'bob', '', '2020-01-01 00:01:00+00:00', 1),
+                (3, 'hi &#38; bye', 'bob', '', '2020-01-01 00:02:00+00:00', 2),
+                (4, 'lol', 'carol', '', '2020-01-01 00:03:00+00:00', 2);
+            INSERT INTO messages_fts (content, username, avatar, messages_rowid)
+            SELECT content, username, avatar, rowid FROM messages;
+            INSERT INTO messages_pages (page, messages_rowid, channel_id)
+            SELECT 0, rowid, channel_id FROM messages;
+            "#,
+        )
+        .unwrap();
+        db
+    }
+
+    fn redaction() -> Redaction {
+        Redaction {
+            user: None,
+            channel: None,
+            sent_at: None,
+            pattern: None,
+            placeholder: false,
+            reason: None,
+            added_at: Utc::now(),
+        }
+    }
+
+    fn rowids(db: &Connection, query: &str) -> Vec<u64> {
+        db.prepare(query)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_redactions() {
+        let db = archive();
+
+        let by_pattern =
+            Redaction { pattern: Some(r"(?i)address|\bhi & bye".to_string()), ..redaction() };
+        let by_user =
+            Redaction { user: Some("carol".to_string()), placeholder: true, ..redaction() };
+        let message = for_message(&db, 1).unwrap().unwrap();
+        assert_eq!(message.channel.as_deref(), Some("general"));
+
+        assert_eq!(apply(&db, &[by_pattern, by_user, message], None).unwrap(), 4);
+
+        // Only the placeholder is left.
+        assert_eq!(rowids(&db, "SELECT rowid FROM messages"), [4]);
+        assert_eq!(rowids(&db, "SELECT messages_rowid FROM messages_pages"), [4]);
+        assert!(rowids(&db, "SELECT messages_rowid FROM messages_fts").is_empty());
+        let (content, redacted): (String, bool) = db
+            .query_row("SELECT content, redacted FROM messages WHERE rowid = 4", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((content.as_str(), redacted), ("", true));
+
+        // Applying redactions again is a no-op.
+        let by_user = Redaction { user: Some("carol".to_string()), ..redaction() };
+        assert_eq!(apply(&db, &[by_user], None).unwrap(), 0);
+
+        assert!(apply(&db, &[redaction()], None).is_err());
+    }
+
+    #[test]
+    fn test_redact_message() {
+        let db = archive();
+        // Messages of other users sent at the same time are kept.
+        db.execute_batch(
+            r#"
+            INSERT INTO messages (rowid, content, username, avatar, sent_at, channel_id)
+            VALUES (5, 'same time', 'carol', '', '2020-01-01 00:00:00+00:00', 1);
+            "#,
+        )
+        .unwrap();
+
+        let message = for_message(&db, 1).unwrap().unwrap();
+        assert_eq!(message.user.as_deref(), Some("alice"));
+        assert!(for_message(&db, 6).unwrap().is_none());
+
+        assert_eq!(apply(&db, &[message], None).unwrap(), 1);
+        assert_eq!(rowids(&db, "SELECT rowid FROM messages WHERE channel_id = 1"), [2, 5]);
+    }
+
+    #[test]
+    fn test_redaction_list() {
+        let dir =
+            std::env::temp_dir().join(format!("amardiscord-redactions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redactions.toml");
+
+        assert!(load(&path).unwrap().is_empty());
+
+        let redaction = Redaction {
+            user: Some("bob".to_string()),
+            reason: Some("GDPR".to_string()),
+            ..redaction()
+        };
+        save(&path, vec![redaction]).unwrap();
+
+        let redactions = load(&path).unwrap();
+        assert_eq!(redactions.len(), 1);
+        assert_eq!(redactions[0].user.as_deref(), Some("bob"));
+        assert_eq!(redactions[0].reason.as_deref(), Some("GDPR"));
+        assert!(redactions[0].channel.is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
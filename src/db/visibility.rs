@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use crate::anonymise::AnonymiseConfig;
+
+/// The `[import]` section of the configuration, deciding which channels of
+/// the backup are imported into the archive, and how.
+///
+/// By default, channels whose category or own permissions deny `@everyone`
+/// are left out. A channel is imported if neither it nor its category is
+/// excluded, and either it or its category is included, or both are visible
+/// to `@everyone`.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportConfig {
+    /// Import channels regardless of their permissions.
+    pub include_private: bool,
+    /// Categories whose channels are imported regardless of permissions.
+    pub include_categories: Vec<String>,
+    /// Channels imported regardless of permissions.
+    pub include_channels: Vec<String>,
+    /// Categories whose channels are never imported.
+    pub exclude_categories: Vec<String>,
+    /// Channels never imported.
+    pub exclude_channels: Vec<String>,
+    /// Whether users are replaced with pseudonyms.
+    pub anonymise: AnonymiseConfig,
+}
+
+/// What has been read so far of a category or channel.
+#[derive(Default, Clone, Debug)]
+pub(crate) struct Facts {
+    pub(crate) name: Option<String>,
+    /// Whether `@everyone` is allowed to view it.
+    pub(crate) public: Option<bool>,
+}
+
+fn contains(names: &[String], name: Option<&str>) -> bool {
+    name.is_some_and(|name| names.iter().any(|n| n == name))
+}
+
+impl ImportConfig {
+    /// Decides whether a channel is imported, given its name and its
+    /// category's, and whether they are visible to `@everyone`.
+    pub(crate) fn is_visible(
+        &self,
+        category: &str,
+        category_public: bool,
+        channel: &str,
+        channel_public: bool,
+    ) -> bool {
+        let (category, channel) = (Some(category), Some(channel));
+
+        if contains(&self.exclude_categories, category) || contains(&self.exclude_channels, channel)
+        {
+            return false;
+        }
+
+        self.include_private
+            || contains(&self.include_categories, category)
+            || contains(&self.include_channels, channel)
+            || (category_public && channel_public)
+    }
+
+    /// Whether a channel is known to be hidden from what has been read of it
+    /// and its category so far, in which case its messages can be skipped.
+    pub(crate) fn is_hidden(&self, category: &Facts, channel: &Facts) -> bool {
+        let (category_name, channel_name) = (category.name.as_deref(), channel.name.as_deref());
+
+        if contains(&self.exclude_categories, category_name)
+            || contains(&self.exclude_channels, channel_name)
+        {
+            return true;
+        }
+
+        // Both names are needed to rule out an include rule.
+        !self.include_private
+            && category_name.is_some_and(|name| !contains(&self.include_categories, Some(name)))
+            && channel_name.is_some_and(|name| !contains(&self.include_channels, Some(name)))
+            && (category.public == Some(false) || channel.public == Some(false))
+    }
+}
+
+/// A permission overwrite of a category or channel, as stored in the backup.
+#[derive(Deserialize)]
+pub(crate) struct Permission {
+    #[serde(rename = "roleName")]
+    role_name: String,
+    allow: String,
+}
+
+/// Whether `@everyone` can view a category or channel with these permission
+/// overwrites.
+pub(crate) fn is_public(permissions: &[Permission]) -> bool {
+    permissions
+        .iter()
+        .find(|permission| permission.role_name == "@everyone")
+        .is_none_or(|permission| permission.allow != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(name: &str, public: Option<bool>) -> Facts {
+        Facts { name: Some(name.to_string()), public }
+    }
+
+    #[test]
+    fn test_visibility() {
+        let config = ImportConfig {
+            include_categories: vec!["Archive".to_string()],
+            include_channels: vec!["announcements".to_string()],
+            exclude_categories: vec!["Moderation".to_string()],
+            exclude_channels: vec!["bot-spam".to_string()],
+            ..Default::default()
+        };
+
+        assert!(config.is_visible("General", true, "general", true));
+        assert!(!config.is_visible("General", true, "staff", false));
+        assert!(!config.is_visible("Private", false, "general", true));
+        assert!(config.is_visible("Private", false, "announcements", false));
+        assert!(config.is_visible("Archive", false, "old", false));
+        assert!(!config.is_visible("General", true, "bot-spam", true));
+        assert!(!config.is_visible("Moderation", true, "announcements", true));
+
+        let include_private = ImportConfig { include_private: true, ..Default::default() };
+        assert!(include_private.is_visible("Private", false, "staff", false));
+
+        // Messages can only be skipped once the channel is known to be hidden.
+        let unknown = Facts::default();
+        assert!(!config.is_hidden(&unknown, &facts("staff", Some(false))));
+        assert!(!config.is_hidden(&facts("General", None), &facts("staff", None)));
+        assert!(config.is_hidden(&facts("General", None), &facts("staff", Some(false))));
+        assert!(!config.is_hidden(&facts("Archive", None), &facts("staff", Some(false))));
+        assert!(config.is_hidden(&unknown, &facts("bot-spam", None)));
+    }
+
+    #[test]
+    fn test_permissions() {
+        let permissions: Vec<Permission> = serde_json::from_str(
+            r#"[
+                { "roleName": "Moderators", "allow": "1024", "deny": "0" },
+                { "roleName": "@everyone", "allow": "0", "deny": "1024" }
+            ]"#,
+        )
+        .expect("Couldn't deserialize permissions");
+
+        assert!(!is_public(&permissions));
+        assert!(is_public(&permissions[..1]));
+    }
+}
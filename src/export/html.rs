@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::access::AccessControl;
+use crate::config::Config;
+use crate::db::Database;
+use crate::export::Error;
+use crate::templates::{ChannelListTemplate, LayoutTemplate, Links, MessagePageTemplate};
+use crate::{anonymise, index, Message, ScrollDirection};
+
+const ASSETS: &[(&str, &str)] = &[
+    ("index.css", include_str!("../static/index.css")),
+    ("index.js", include_str!("../static/index.js")),
+    ("htmx.min.js", include_str!("../static/htmx.min.js")),
+    ("static.js", include_str!("../static/static.js")),
+];
+
+/// Number of messages in each shard of the search index.
+const SEARCH_SHARD_SIZE: usize = 20_000;
+
+/// Channel id, page, message id, username, avatar, time and text of a message.
+type IndexedMessage = (u64, u64, u64, String, String, String, String);
+
+/// Lists the shards of the messages searched by `static.js`. The index and its
+/// shards are loaded as scripts so that search also works when the site is
+/// opened from the file system.
+#[derive(Serialize, Default)]
+struct SearchIndex {
+    channels: BTreeMap<u64, String>,
+    /// Paths of the shards, relative to the root of the site.
+    shards: Vec<String>,
+}
+
+/// Writes the messages of the search index to shards as they're exported, so
+/// that neither the export nor the browser holds every message at once.
+struct SearchShards<'a> {
+    output: &'a Path,
+    /// Messages of the shard being filled, in the order of the pages.
+    messages: Vec<IndexedMessage>,
+    paths: Vec<String>,
+}
+
+impl SearchShards<'_> {
+    fn push(&mut self, message: IndexedMessage) -> Result<(), Error> {
+        self.messages.push(message);
+
+        if self.messages.len() >= SEARCH_SHARD_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.messages.is_empty() {
+            return Ok(());
+        }
+
+        let path = format!("search/{}.js", self.paths.len());
+        write_script(&self.output.join(&path), "searchShard", &self.messages)?;
+        self.messages.clear();
+        self.paths.push(path);
+
+        Ok(())
+    }
+}
+
+/// Renders the channels that anonymous visitors can view to a static site in
+/// `output`, which works without the server.
+///
+/// Pages are written to `channel/<id>/<page>.html`, along with the assets, the
+/// identicons of anonymised users and a search index.
+pub fn export(output: &Path, config: &Config) -> Result<(), Error> {
+    let db = Database::new(false)?;
+    let channels = db.get_channel_list()?;
+    let access = AccessControl::new(&config.auth, &config.access, &channels)?;
+    let channel_list = access.anonymous().filter_channel_list(channels);
+    let default_channel = config.index.default_channel.as_deref();
+
+    fs::create_dir_all(output)?;
+    for (name, content) in ASSETS {
+        fs::write(output.join(name), content)?;
+    }
+
+    fs::create_dir_all(output.join("search"))?;
+    let mut index = SearchIndex::default();
+    let mut shards = SearchShards { output, messages: Vec::new(), paths: Vec::new() };
+    let mut identicons = BTreeSet::new();
+    let mut pages = 0;
+
+    for category in &channel_list.categories {
+        for channel in &category.channels {
+            let links = Links::Static("../../");
+            let channels = ChannelListTemplate::render(&channel_list, Some(channel.id), links);
+            let page_count = db.get_page_count(channel.id)?;
+
+            let dir = output.join("channel").join(channel.id.to_string());
+            fs::create_dir_all(&dir)?;
+
+            // Empty channels still get a page, so that links to them work.
+            for page in 0..page_count.max(1) {
+                let messages = db.get_page(channel.id, page)?;
+                let html = render_page(
+                    &messages,
+                    &channel.name,
+                    channel.id,
+                    page,
+                    page_count,
+                    links,
+                    &channels,
+                );
+                fs::write(dir.join(format!("{page}.html")), html)?;
+                pages += 1;
+
+                for message in messages.iter().filter(|message| !message.redacted) {
+                    if let Some(file) = message.avatar.strip_prefix("/identicon/") {
+                        identicons.insert(file.to_string());
+                    }
+
+                    shards.push((
+                        channel.id,
+                        page,
+                        message.rowid,
+                        message.username.clone(),
+                        message.avatar.clone(),
+                        message.sent_at.to_string(),
+                        message.content.plain_text(),
+                    ))?;
+                }
+            }
+
+            index.channels.insert(channel.id, channel.name.clone());
+        }
+    }
+
+    // The home page shows the latest messages of the default channel.
+    if let Some(channel) = index::default_channel(&channel_list, default_channel) {
+        let links = Links::Static("");
+        let channels = ChannelListTemplate::render(&channel_list, Some(channel.id), links);
+        let page_count = db.get_page_count(channel.id)?;
+        let messages = db.get_page(channel.id, 0)?;
+        let html =
+            render_page(&messages, &channel.name, channel.id, 0, page_count, links, &channels);
+        fs::write(output.join("index.html"), html)?;
+    }
+
+    if !identicons.is_empty() {
+        fs::create_dir_all(output.join("identicon"))?;
+    }
+    for file in identicons {
+        if let Some(svg) = file.strip_suffix(".svg").and_then(anonymise::identicon) {
+            fs::write(output.join("identicon").join(file), svg)?;
+        }
+    }
+
+    shards.flush()?;
+    index.shards = shards.paths;
+    write_script(&output.join("search-index.js"), "searchIndex", &index)?;
+
+    info!("Exported {pages} pages of {} channels to {output:?}.", index.channels.len());
+
+    Ok(())
+}
+
+// Writes a script assigning `value` to `window[name]`.
+fn write_script(path: &Path, name: &str, value: &impl Serialize) -> Result<(), Error> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    write!(writer, "window.{name} = ")?;
+    serde_json::to_writer(&mut writer, value)?;
+    writer.write_all(b";\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Renders a page as a whole document, linking to the older and newer pages
+// that exist.
+fn render_page(
+    messages: &[Message],
+    channel_name: &str,
+    channel_id: u64,
+    page: u64,
+    page_count: u64,
+    links: Links,
+    channels: &str,
+) -> String {
+    let direction = match (page + 1 < page_count, page > 0) {
+        (true, true) => ScrollDirection::Both,
+        (true, false) => ScrollDirection::Up,
+        (false, true) => ScrollDirection::Down,
+        (false, false) => ScrollDirection::Unspecified,
+    };
+
+    let content = MessagePageTemplate::render(
+        messages,
+        channel_id,
+        channel_name.to_string(),
+        page,
+        direction,
+        None,
+        links,
+    );
+
+    LayoutTemplate::render(channel_name, &content, links, Some(channels))
+}
@@ -0,0 +1,205 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Database, MessageFilter};
+use crate::export::Error;
+use crate::{Channel, Message};
+
+/// A format the messages of a channel can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One line per message, with multi-line messages continued on indented
+    /// lines.
+    Text,
+    /// Messages grouped by author under a bold header.
+    Markdown,
+    /// An array of message objects.
+    Json,
+    /// One row per message, with a header row.
+    Csv,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Markdown => "md",
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => "text/plain; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parses a format from its file extension.
+    fn from_str(extension: &str) -> Result<Self, Self::Err> {
+        match extension {
+            "txt" => Ok(Format::Text),
+            "md" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown export format {extension:?}, expected txt, md, json or csv")),
+        }
+    }
+}
+
+/// Which messages of a channel are exported.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExportQuery {
+    /// First day of messages, in UTC.
+    pub from: Option<NaiveDate>,
+    /// Last day of messages, in UTC.
+    pub to: Option<NaiveDate>,
+    /// Name of the author of the messages.
+    pub user: Option<String>,
+}
+
+impl ExportQuery {
+    pub fn filter(self) -> MessageFilter {
+        let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0).map(|time| time.and_utc());
+
+        MessageFilter {
+            since: self.from.and_then(midnight),
+            until: self.to.and_then(|to| to.checked_add_days(Days::new(1))).and_then(midnight),
+            username: self.user,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    id: u64,
+    username: &'a str,
+    avatar: &'a str,
+    #[serde(rename = "sentAt")]
+    sent_at: DateTime<Utc>,
+    content: String,
+}
+
+/// Writes the messages of a channel matching `filter`, oldest first, with their
+/// content converted back to plain text.
+pub fn write_channel(
+    db: &Database,
+    channel: &Channel,
+    filter: &MessageFilter,
+    format: Format,
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let mut previous_username = None;
+    let mut first = true;
+
+    match format {
+        Format::Markdown => write!(writer, "# {}\n\n", escape_markdown(&channel.name))?,
+        Format::Json => writer.write_all(b"[")?,
+        Format::Csv => writer.write_all(b"id,sent_at,username,avatar,content\r\n")?,
+        Format::Text => {},
+    }
+
+    db.for_each_message(channel.channel_id, filter, |message| {
+        let Message { username, avatar, sent_at, rowid, content, .. } = &message;
+        let text = content.plain_text();
+
+        match format {
+            Format::Text => {
+                writeln!(writer, "[{sent_at}] {username}: {}", text.replace('\n', "\n    "))?
+            },
+            Format::Markdown => {
+                // Consecutive messages of a user are grouped, as in the archive.
+                if previous_username.as_ref() != Some(username) {
+                    write!(writer, "**{}** — {sent_at}\n\n", escape_markdown(username))?;
+                    previous_username = Some(username.clone());
+                }
+                write!(writer, "{text}\n\n")?;
+            },
+            Format::Json => {
+                writer.write_all(if first { b"\n" } else { b",\n" })?;
+                let message =
+                    JsonMessage { id: *rowid, username, avatar, sent_at: *sent_at, content: text };
+                serde_json::to_writer(&mut writer, &message)?;
+            },
+            Format::Csv => {
+                let row = [
+                    rowid.to_string(),
+                    sent_at.to_rfc3339(),
+                    username.clone(),
+                    avatar.clone(),
+                    text,
+                ];
+                write!(writer, "{}\r\n", row.iter().map(|field| escape_csv(field)).join(","))?;
+            },
+        }
+
+        first = false;
+        Ok(())
+    })?;
+
+    if format == Format::Json {
+        writer.write_all(if first { b"]\n" } else { b"\n]\n" })?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Quotes a field if needed, as in RFC 4180.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Escapes the characters that would change the formatting of a name.
+fn escape_markdown(name: &str) -> String {
+    name.chars().fold(String::new(), |mut escaped, c| {
+        if matches!(c, '\\' | '*' | '_' | '`' | '~' | '[' | ']' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape_csv("hello"), "hello");
+        assert_eq!(escape_csv("a \"quote\", and\nmore"), "\"a \"\"quote\"\", and\nmore\"");
+        assert_eq!(escape_markdown("*star*_name"), "\\*star\\*\\_name");
+    }
+
+    #[test]
+    fn test_export_query() {
+        let query: ExportQuery =
+            serde_json::from_str(r#"{ "from": "2020-01-01", "to": "2020-01-31", "user": "bob" }"#)
+                .unwrap();
+        let filter = query.filter();
+
+        assert_eq!(filter.since.unwrap().to_rfc3339(), "2020-01-01T00:00:00+00:00");
+        assert_eq!(filter.until.unwrap().to_rfc3339(), "2020-02-01T00:00:00+00:00");
+        assert_eq!(filter.username.as_deref(), Some("bob"));
+        assert_eq!("md".parse(), Ok(Format::Markdown));
+        assert!("html".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+use crate::{access, db};
+
+pub mod html;
+pub mod messages;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("reading archive: {0}")]
+    Database(#[from] db::Error),
+    #[error("channel {0} doesn't exist")]
+    UnknownChannel(u64),
+    #[error("configuring access control: {0}")]
+    AccessControl(#[from] access::Error),
+    #[error("writing export: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serializing search index: {0}")]
+    Json(#[from] serde_json::Error),
+}
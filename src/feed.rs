@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::Message;
+
+/// Number of messages in a feed, the latest ones.
+pub const FEED_SIZE: usize = 50;
+
+/// Length of the titles of entries, in characters, beyond which the content
+/// of the message is cut.
+const TITLE_LENGTH: usize = 80;
+
+/// An Atom feed of the latest messages of a channel, a user or a search.
+pub struct Feed {
+    pub title: String,
+    /// Path of the feed itself, which identifies it.
+    pub path: String,
+    /// Path of the page showing the same messages.
+    pub page: String,
+    /// Entries of the feed, the latest first.
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Identifies the feed by its URL under `base_url`, the public URL of the
+    /// server. Without it, feeds are identified by their path alone, as the
+    /// address the server was reached at may change.
+    pub fn id(&self, base_url: Option<&str>) -> String {
+        match base_url {
+            Some(base_url) => format!("{base_url}{}", self.path),
+            None => format!("urn:amardiscord:feed:{}", hex_digest(self.path.as_bytes())),
+        }
+    }
+
+    /// When the latest message of the feed was sent. Feeds without messages
+    /// never changed.
+    pub fn updated(&self) -> DateTime<Utc> {
+        self.entries.iter().map(|entry| entry.message.sent_at).max().unwrap_or_default()
+    }
+}
+
+/// A message of a feed, along with the channel it was sent in.
+pub struct FeedEntry {
+    pub channel_name: String,
+    pub message: Message,
+}
+
+impl FeedEntry {
+    /// Identifies the message by its channel, author and date, which unlike
+    /// its rowid don't change when the archive is rebuilt.
+    pub fn id(&self) -> String {
+        let mut key = Vec::new();
+        key.extend(self.channel_name.as_bytes());
+        key.push(0);
+        key.extend(self.message.username.as_bytes());
+        key.push(0);
+        key.extend(self.message.sent_at.timestamp_millis().to_be_bytes());
+
+        format!("urn:amardiscord:message:{}", hex_digest(&key))
+    }
+
+    /// The author and channel of the message, followed by the start of its
+    /// text.
+    pub fn title(&self) -> String {
+        let text = self.message.content.plain_text();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let mut title = format!("{} in #{}", self.message.username, self.channel_name);
+        if !text.is_empty() {
+            title.push_str(": ");
+            title.extend(text.chars().take(TITLE_LENGTH));
+            if text.chars().count() > TITLE_LENGTH {
+                title.push('…');
+            }
+        }
+
+        title
+    }
+}
+
+// The start of the SHA-256 digest of `bytes`, in hexadecimal.
+fn hex_digest(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    digest[..16].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageContent;
+
+    fn entry(channel_name: &str, rowid: u64, text: &str) -> FeedEntry {
+        FeedEntry {
+            channel_name: channel_name.to_string(),
+            message: Message {
+                content: MessageContent::from_text(text),
+                username: "Shy Lynx".to_string(),
+                avatar: String::new(),
+                sent_at: DateTime::from_timestamp(1_600_000_000, 0).unwrap(),
+                rowid,
+                redacted: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_entry_id() {
+        let id = entry("general", 1, "Hello").id();
+        assert_eq!(id.len(), "urn:amardiscord:message:".len() + 32);
+
+        // Ids survive rebuilds of the archive and redactions of the content.
+        assert_eq!(entry("general", 2, "Hello <3").id(), id);
+        assert_ne!(entry("routes", 1, "Hello").id(), id);
+    }
+
+    #[test]
+    fn test_feed_id() {
+        let feed = Feed {
+            title: "#general".to_string(),
+            path: "/channel/1/feed.atom".to_string(),
+            page: "/channel/1/0".to_string(),
+            entries: Vec::new(),
+        };
+
+        assert_eq!(
+            feed.id(Some("https://archive.example.com")),
+            "https://archive.example.com/channel/1/feed.atom"
+        );
+        assert_eq!(feed.id(None).len(), "urn:amardiscord:feed:".len() + 32);
+    }
+
+    #[test]
+    fn test_entry_title() {
+        assert_eq!(entry("general", 1, "  a <b>\n c ").title(), "Shy Lynx in #general: a <b> c");
+        assert_eq!(entry("general", 1, "").title(), "Shy Lynx in #general");
+
+        let title = entry("general", 1, &"a".repeat(100)).title();
+        assert!(title.ends_with(&format!(": {}…", "a".repeat(TITLE_LENGTH))));
+    }
+}
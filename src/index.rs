@@ -0,0 +1,84 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::{ChannelList, ChannelListEntry};
+
+/// Number of days before the last message of the archive whose activity is
+/// summarised on the index page.
+pub const RECENT_DAYS: u64 = 30;
+
+/// Number of channels listed by [`ArchiveSummary::recent_channels`].
+pub const RECENT_CHANNELS: usize = 10;
+
+/// The `[index]` section of the configuration.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexConfig {
+    /// Name of the archived server, shown on the index page.
+    pub name: Option<String>,
+    /// Name of the channel shown first. Defaults to the first channel of the
+    /// channel list.
+    pub default_channel: Option<String>,
+    /// Public URL of the server, e.g. `https://archive.example.com`, which
+    /// feeds need for their links to be absolute.
+    pub base_url: Option<String>,
+}
+
+/// Number of messages sent recently in a channel.
+pub struct RecentChannel {
+    pub channel_id: u64,
+    pub name: String,
+    pub messages: u64,
+}
+
+/// Overview of the channels the viewer can view, shown on the index page.
+pub struct ArchiveSummary {
+    /// Number of messages, leaving out redaction placeholders.
+    pub messages: u64,
+    pub channels: u64,
+    /// Days of the first and last messages, in UTC, unless there aren't any.
+    pub first_day: Option<NaiveDate>,
+    pub last_day: Option<NaiveDate>,
+    /// The most active channels during the [`RECENT_DAYS`] before the last
+    /// message, the most active first.
+    pub recent_channels: Vec<RecentChannel>,
+}
+
+/// The channel shown first: the configured one if it's in the channel list
+/// of the viewer, the first channel of the list otherwise.
+pub fn default_channel<'a>(
+    channel_list: &'a ChannelList,
+    name: Option<&str>,
+) -> Option<&'a ChannelListEntry> {
+    let mut channels = channel_list.categories.iter().flat_map(|category| &category.channels);
+
+    name.and_then(|name| channels.clone().find(|channel| channel.name == name))
+        .or_else(|| channels.next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChannelCategory;
+
+    #[test]
+    fn test_default_channel() {
+        let channel =
+            |id: u64, name: &str| ChannelListEntry { name: name.to_string(), id, channel_type: 0 };
+        let channel_list = ChannelList {
+            categories: vec![
+                ChannelCategory { name: "Empty".to_string(), channels: Vec::new() },
+                ChannelCategory {
+                    name: "Text channels".to_string(),
+                    channels: vec![channel(3, "rules"), channel(5, "announcements")],
+                },
+            ],
+        };
+
+        let id = |name| default_channel(&channel_list, name).map(|channel| channel.id);
+        assert_eq!(id(Some("announcements")), Some(5));
+        assert_eq!(id(Some("mod-chat")), Some(3));
+        assert_eq!(id(None), Some(3));
+        assert_eq!(default_channel(&ChannelList::default(), None).map(|c| c.id), None);
+    }
+}
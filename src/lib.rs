@@ -4,26 +4,37 @@ use askama_escape::escape_html;
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use regex::{Captures, Regex};
-use serde::Deserialize;
-
+use serde::{Deserialize, Serialize};
+
+pub mod access;
+pub mod annotations;
+pub mod anonymise;
+pub mod calendar;
+pub mod collections;
+pub mod config;
 pub mod db;
+pub mod export;
+pub mod feed;
+pub mod index;
+pub mod links;
 pub mod search;
 pub mod serve;
+mod side_db;
+pub mod stats;
+pub mod telemetry;
 pub mod templates;
+pub mod user;
 
 pub const SQLITE_ARCHIVE_PATH: &str = "./data/amardiscord.sqlite";
 
-#[derive(Default)]
-pub struct Content {
-    pub categories: Vec<Category>,
-    pub channels: Vec<Channel>,
-}
+/// List of redactions applied to every build of the archive.
+pub const REDACTIONS_PATH: &str = "./data/redactions.toml";
 
-#[derive(Deserialize, Debug)]
-pub struct Category {
-    pub name: String,
-    pub children: Vec<Channel>,
-}
+/// Default location of the collections of messages kept by users.
+pub const COLLECTIONS_PATH: &str = "./data/collections.sqlite";
+
+/// Default location of the annotations of messages.
+pub const ANNOTATIONS_PATH: &str = "./data/annotations.sqlite";
 
 #[derive(Deserialize, Debug)]
 pub struct Channel {
@@ -44,11 +55,60 @@ pub struct Message {
     pub sent_at: DateTime<Utc>,
     #[serde(skip)]
     pub rowid: u64,
+    /// Whether the message was redacted, leaving a placeholder.
+    #[serde(skip)]
+    pub redacted: bool,
+}
+
+/// Identifies a message by its channel, time and author, which unlike its
+/// rowid don't change when the archive is rebuilt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MessageKey {
+    /// Name of the channel of the message.
+    pub channel: String,
+    pub sent_at: DateTime<Utc>,
+    pub username: String,
 }
 
 #[derive(Debug)]
 pub struct MessageContent(String);
 
+impl MessageContent {
+    /// The content as it was typed, with HTML entities unescaped and emotes
+    /// written as `:name:`.
+    pub fn plain_text(&self) -> String {
+        static RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r#"<img class="emote" alt="(\w+)" src="[^"]*"/>"#).unwrap());
+
+        RE.replace_all(&self.0, ":$1:")
+            .replace("&#60;", "<")
+            .replace("&#62;", ">")
+            .replace("&#34;", "\"")
+            .replace("&#39;", "'")
+            .replace("&#38;", "&")
+    }
+
+    /// Escapes the text of a message, as written in Discord, to HTML.
+    pub(crate) fn from_text(input: &str) -> Self {
+        static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"&#60;(a?):(\w+):(\d+)&#62;").unwrap());
+
+        let mut escaped = String::new();
+        escape_html(&mut escaped, input).unwrap();
+
+        MessageContent(
+            RE.replace_all(&escaped, |captures: &Captures| {
+                let ext = if &captures[1] == "a" { "gif" } else { "png" };
+                let emote_name = &captures[2];
+                let emote_id = &captures[3];
+                format!(
+                    r#"<img class="emote" alt="{emote_name}" src="https://cdn.discordapp.com/emojis/{emote_id}.{ext}"/>"#,
+                )
+            })
+            .into_owned(),
+        )
+    }
+}
+
 impl AsRef<str> for MessageContent {
     fn as_ref(&self) -> &str {
         &self.0
@@ -63,8 +123,8 @@ impl Display for MessageContent {
 
 // This deserialization implementation is going to get used only when building
 // the initial SQLite database. It will first deserialize its input as a string,
-// then escape the HTML entities, then replace the (now escaped; see regex
-// below) instances of emote tags with the equivalent HTML `img` element.
+// then escape the HTML entities, then replace the (now escaped; see regex in
+// `from_text`) instances of emote tags with the equivalent HTML `img` element.
 //
 // Discord emote tags are of the form `<a:FrankerZ:12345678>`. If the `a`
 // character in the first field is present, the emote is an animated gif and
@@ -78,23 +138,8 @@ impl<'de> Deserialize<'de> for MessageContent {
     where
         D: serde::Deserializer<'de>,
     {
-        static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"&#60;(a?):(\w+):(\d+)&#62;").unwrap());
-
         let input = String::deserialize(deserializer)?;
-        let mut escaped = String::new();
-        escape_html(&mut escaped, &input).unwrap();
-
-        Ok(MessageContent(
-            RE.replace_all(&escaped, |captures: &Captures| {
-                let ext = if &captures[1] == "a" { "gif" } else { "png" };
-                let emote_name = &captures[2];
-                let emote_id = &captures[3];
-                format!(
-                    r#"<img class="emote" alt="{emote_name}" src="https://cdn.discordapp.com/emojis/{emote_id}.{ext}"/>"#,
-                )
-            })
-            .into_owned(),
-        ))
+        Ok(MessageContent::from_text(&input))
     }
 }
 
@@ -141,4 +186,13 @@ mod tests {
             r#"FrankerZ looks like <img class="emote" alt="FrankerZ" src="https://cdn.discordapp.com/emojis/245226326636757002.png"/>"#
         );
     }
+
+    #[test]
+    fn test_plain_text() {
+        let message_content: MessageContent =
+            serde_json::from_str(r#""<b>&amp;</b> \"quoted\" <a:FrankerZ:1234>""#)
+                .expect("Couldn't deserialize message content");
+
+        assert_eq!(message_content.plain_text(), r#"<b>&amp;</b> "quoted" :FrankerZ:"#);
+    }
 }
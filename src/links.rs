@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+/// Number of links listed per page.
+pub const LINKS_PER_PAGE: u64 = 100;
+
+/// Number of domains listed as filters.
+pub const TOP_DOMAINS: usize = 30;
+
+/// Narrows down the links of the archive.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LinksQuery {
+    /// Only the links to this domain or its subdomains, e.g. `youtube.com`.
+    pub domain: Option<String>,
+    /// Only the links posted in this channel.
+    pub channel: Option<u64>,
+    #[serde(default)]
+    pub page: u64,
+}
+
+impl LinksQuery {
+    /// The query string of the links page, including the leading `?` unless
+    /// it's empty.
+    pub fn query_string(&self) -> String {
+        let params = [
+            self.domain
+                .as_ref()
+                .map(|domain| format!("domain={}", utf8_percent_encode(domain, NON_ALPHANUMERIC))),
+            self.channel.map(|channel| format!("channel={channel}")),
+            (self.page > 0).then(|| format!("page={}", self.page)),
+        ];
+        let params = params.into_iter().flatten().collect::<Vec<_>>();
+
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+/// A URL posted in a message.
+pub struct Link {
+    pub url: String,
+    pub domain: String,
+    pub channel_id: u64,
+    pub channel_name: String,
+    pub message_rowid: u64,
+    pub username: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// A page of links, the latest first.
+pub struct LinkPage {
+    pub links: Vec<Link>,
+    /// Whether there are older links.
+    pub more: bool,
+    /// The domains with the most links, along with their number of links,
+    /// regardless of the queried domain and page.
+    pub domains: Vec<(String, u64)>,
+}
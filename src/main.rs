@@ -1,38 +1,443 @@
-use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
-use tracing::{error, info};
-use tracing_subscriber::filter::LevelFilter;
+use amardiscord::config::Config;
+use amardiscord::db::{ArchiveStatus, Database, Redaction, SCHEMA_VERSION};
+use amardiscord::export::messages::{self, ExportQuery, Format};
+use amardiscord::{access, export, serve, REDACTIONS_PATH};
+use chrono::{NaiveDate, Utc};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
-#[clap(name = "amardiscord")]
+#[clap(name = "amardiscord", args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Path to the Discord backup directory (default: `./data`).
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    serve: ServeArgs,
+    /// Log filter, either a level (e.g. `debug`) or a list of directives
+    /// (e.g. `info,amardiscord::db=debug`).
+    #[clap(long, global = true, default_value = "info")]
+    log_level: String,
+    /// Log output format.
+    #[clap(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build the database if needed and serve the archive (default).
+    Serve(ServeArgs),
+    /// Read a password from the standard input and print its hash, for the
+    /// `[[auth.users]]` section of the configuration file.
+    HashPassword,
+    /// Remove messages from the archive, and from every future build of it.
+    Redact(RedactArgs),
+    /// Export the archive to other formats.
+    #[clap(subcommand)]
+    Export(ExportCommand),
+}
+
+#[derive(Subcommand)]
+enum ExportCommand {
+    /// Render the channels that anonymous visitors can view to a static site,
+    /// searchable without the server.
+    Html(HtmlExportArgs),
+    /// Write the messages of a channel, identified by the number in its link
+    /// (`/channel/<id>/<page>`), as text, Markdown, JSON or CSV.
+    Channel(ChannelExportArgs),
+}
+
+#[derive(Args)]
+struct ChannelExportArgs {
+    channel: u64,
+    /// Format of the export: `txt`, `md`, `json` or `csv`.
+    #[clap(long, default_value = "txt")]
+    format: Format,
+    /// File the export is written to, instead of the standard output.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+    /// Only export the messages sent on or after this day (`YYYY-MM-DD`).
+    #[clap(long)]
+    from: Option<NaiveDate>,
+    /// Only export the messages sent on or before this day (`YYYY-MM-DD`).
+    #[clap(long)]
+    to: Option<NaiveDate>,
+    /// Only export the messages of this user.
+    #[clap(long)]
+    user: Option<String>,
+}
+
+#[derive(Args)]
+struct HtmlExportArgs {
+    #[clap(flatten)]
+    archive: ArchiveArgs,
+    /// Directory the site is written to.
+    #[clap(long, short)]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+#[clap(group(
+    ArgGroup::new("target").required(true).multiple(true).args(["user", "message", "channel", "pattern"])
+))]
+struct RedactArgs {
+    /// Remove the messages of this user.
+    #[clap(long)]
+    user: Option<String>,
+    /// Remove a single message, identified by the number in its link
+    /// (`/message/<id>`).
+    #[clap(long, conflicts_with_all = ["user", "channel"])]
+    message: Option<u64>,
+    /// Remove the messages of this channel.
+    #[clap(long)]
+    channel: Option<String>,
+    /// Remove the messages whose text matches this regular expression.
+    #[clap(long)]
+    pattern: Option<String>,
+    /// Show a "Message removed" placeholder instead of the messages.
+    #[clap(long)]
+    placeholder: bool,
+    /// Why the messages are removed, recorded in the redaction list.
+    #[clap(long)]
+    reason: Option<String>,
+    /// Path to the configuration file, to redact anonymised users by their
+    /// name rather than their pseudonym.
+    #[clap(long)]
+    config: Option<PathBuf>,
+}
+
+/// Where the archive is built from.
+#[derive(Args)]
+struct ArchiveArgs {
+    /// Path to the directory holding the Discord backups (default: `./data`).
     path: Option<PathBuf>,
+    /// What to do if the backup changed since the database was built.
+    #[clap(long, value_enum, default_value_t = StalePolicy::Rebuild)]
+    on_stale: StalePolicy,
+    /// Path to the configuration file, setting up the import, authentication
+    /// and access rules.
+    #[clap(long)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[clap(flatten)]
+    archive: ArchiveArgs,
+    /// Assume the database file is never modified while serving (e.g. on a
+    /// read-only mount), which disables file locking.
+    #[clap(long)]
+    immutable: bool,
+    /// Time in seconds after which requests are aborted.
+    #[clap(long, default_value_t = 30)]
+    request_timeout: u64,
+    /// Maximum number of requests handled concurrently.
+    #[clap(long, default_value_t = 256)]
+    max_concurrent_requests: usize,
+    /// Maximum number of channel exports streamed concurrently.
+    #[clap(long, default_value_t = 4)]
+    max_concurrent_exports: usize,
+    /// Time in seconds after which exports are aborted if the client stops
+    /// reading them.
+    #[clap(long, default_value_t = 30)]
+    export_send_timeout: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, including the fields of the current spans.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum StalePolicy {
+    /// Rebuild the database from the new backup.
+    Rebuild,
+    /// Exit without serving the stale database.
+    Refuse,
+    /// Log a warning and serve the stale database.
+    Warn,
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(LevelFilter::INFO)
+    let Cli { command, serve, log_level, log_format } = Cli::parse();
+
+    let filter = match EnvFilter::try_new(&log_level) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Invalid log level {log_level:?}: {e}");
+            return;
+        },
+    };
+
+    // Logs go to the standard error, leaving the standard output to exports.
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_env_filter(filter)
         .with_thread_ids(true)
         .with_file(true)
         .with_line_number(true)
-        .with_thread_names(true)
-        .init();
+        .with_thread_names(true);
 
-    let Cli { path } = Cli::parse();
+    match log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(false).init(),
+    }
 
-    if !Path::new(amardiscord::SQLITE_ARCHIVE_PATH).exists() {
-        info!("Database file doesn't exist. Building it.");
+    match command.unwrap_or(Command::Serve(serve)) {
+        Command::Serve(args) => run_server(args).await,
+        Command::HashPassword => hash_password(),
+        Command::Redact(args) => redact(args),
+        Command::Export(ExportCommand::Html(args)) => export_html(args).await,
+        Command::Export(ExportCommand::Channel(args)) => export_channel(args),
+    }
+}
 
-        if let Err(e) = amardiscord::db::build(path).await {
-            error!("Building database: {e}");
-            return;
+fn redact(args: RedactArgs) {
+    let RedactArgs { user, message, channel, pattern, placeholder, reason, config } = args;
+
+    let Some(config) = load_config(config) else {
+        return;
+    };
+
+    let target = match message {
+        Some(rowid) => match amardiscord::db::message_redaction(rowid) {
+            Ok(redaction) => redaction,
+            Err(e) => {
+                error!("Finding message: {e}");
+                return;
+            },
+        },
+        None => Redaction {
+            user,
+            channel,
+            sent_at: None,
+            pattern: None,
+            placeholder: false,
+            reason: None,
+            added_at: Utc::now(),
+        },
+    };
+
+    let redaction = Redaction { pattern, placeholder, reason, ..target };
+
+    match amardiscord::db::redact(redaction, &config.import.anonymise) {
+        Ok(redacted) => {
+            info!("Added the redaction to {REDACTIONS_PATH}.");
+            // Immutable connections cache pages that may not exist anymore.
+            if redacted > 0 {
+                warn!(
+                    "Restart the servers running with --immutable to serve the redacted archive."
+                );
+            }
+        },
+        Err(e) => error!("Redacting messages: {e}"),
+    }
+}
+
+// Loads the configuration file, if any, logging errors.
+fn load_config(path: Option<PathBuf>) -> Option<Config> {
+    match path.map(|path| Config::load(&path)).transpose() {
+        Ok(config) => Some(config.unwrap_or_default()),
+        Err(e) => {
+            error!("Loading configuration: {e}");
+            None
+        },
+    }
+}
+
+fn hash_password() {
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().lock().read_line(&mut password) {
+        error!("Reading password: {e}");
+        return;
+    }
+
+    match access::hash_password(password.trim_end_matches(['\r', '\n'])) {
+        Ok(hash) => println!("{hash}"),
+        Err(e) => error!("{e}"),
+    }
+}
+
+fn export_channel(args: ChannelExportArgs) {
+    if let Err(e) = write_channel_export(args) {
+        error!("Exporting channel: {e}");
+    }
+}
+
+fn write_channel_export(args: ChannelExportArgs) -> Result<(), export::Error> {
+    let ChannelExportArgs { channel, format, output, from, to, user } = args;
+
+    let db = Database::new(false)?;
+    let channel = db.get_channel(channel).map_err(|e| {
+        if e.is_not_found() {
+            export::Error::UnknownChannel(channel)
+        } else {
+            e.into()
         }
+    })?;
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let filter = ExportQuery { from, to, user }.filter();
+    messages::write_channel(&db, &channel, &filter, format, BufWriter::new(writer))
+}
+
+async fn export_html(args: HtmlExportArgs) {
+    let HtmlExportArgs { archive, output } = args;
+
+    let Some(config) = prepare_archive(archive, false).await else {
+        return;
+    };
+
+    if let Err(e) = amardiscord::export::html::export(&output, &config) {
+        error!("Exporting archive: {e}");
     }
+}
 
-    if let Err(e) = amardiscord::serve::serve().await {
+async fn run_server(args: ServeArgs) {
+    let ServeArgs {
+        archive,
+        immutable,
+        request_timeout,
+        max_concurrent_requests,
+        max_concurrent_exports,
+        export_send_timeout,
+    } = args;
+
+    let Some(config) = prepare_archive(archive, immutable).await else {
+        return;
+    };
+
+    let options = serve::Options {
+        immutable,
+        request_timeout: Duration::from_secs(request_timeout),
+        max_concurrent_requests,
+        max_concurrent_exports,
+        export_send_timeout: Duration::from_secs(export_send_timeout),
+        config,
+    };
+
+    if let Err(e) = amardiscord::serve::serve(options).await {
         error!("Server error: {e}");
     }
 }
+
+// Builds, migrates or rebuilds the archive as needed, returning the
+// configuration if it's ready to be read.
+//
+// Immutable archives, which SQLite assumes never change, and archives that
+// can't be written to are never changed: they must be ready to be read.
+async fn prepare_archive(args: ArchiveArgs, immutable: bool) -> Option<Config> {
+    let ArchiveArgs { path, on_stale, config } = args;
+
+    let config = load_config(config)?;
+
+    let path = path.unwrap_or_else(|| PathBuf::from("./data"));
+
+    let read_only = if immutable {
+        Some("with --immutable")
+    } else if !amardiscord::db::archive_is_writable() {
+        Some("as it is read-only")
+    } else {
+        None
+    };
+
+    let rebuild = match amardiscord::db::archive_status() {
+        Ok(ArchiveStatus::Missing) => {
+            if let Some(read_only) = read_only {
+                error!("Database file doesn't exist, and it can't be built {read_only}.");
+                return None;
+            }
+            info!("Database file doesn't exist. Building it.");
+            true
+        },
+        Ok(ArchiveStatus::Invalid(reason)) => {
+            if let Some(read_only) = read_only {
+                error!(
+                    "Database file is not a complete archive ({reason}), and it can't be rebuilt \
+                     {read_only}."
+                );
+                return None;
+            }
+            warn!("Database file is not a complete archive ({reason}). Rebuilding it.");
+            true
+        },
+        Ok(ArchiveStatus::Complete { fingerprint, schema_version }) => {
+            if schema_version < SCHEMA_VERSION {
+                if let Some(read_only) = read_only {
+                    error!(
+                        "Database schema version {schema_version} is older than {SCHEMA_VERSION}, \
+                         and it can't be migrated {read_only}. Start amardiscord once with write \
+                         access to migrate it."
+                    );
+                    return None;
+                }
+                info!("Migrating database from schema version {schema_version}.");
+
+                if let Err(e) = amardiscord::db::migrate() {
+                    error!("Migrating database: {e}");
+                    return None;
+                }
+            }
+
+            match amardiscord::db::source_fingerprint(&path, &config.import) {
+                Ok(Some(current)) if current != fingerprint => match on_stale {
+                    StalePolicy::Rebuild => match read_only {
+                        Some(read_only) => {
+                            error!(
+                                "Backup changed since the database was built, and it can't be \
+                                 rebuilt {read_only}. Pass --on-stale warn to serve it anyway."
+                            );
+                            return None;
+                        },
+                        None => {
+                            info!("Backup changed since the database was built. Rebuilding it.");
+                            true
+                        },
+                    },
+                    StalePolicy::Refuse => {
+                        error!("Backup changed since the database was built. Not serving it.");
+                        return None;
+                    },
+                    StalePolicy::Warn => {
+                        warn!("Backup changed since the database was built. Serving it anyway.");
+                        false
+                    },
+                },
+                Ok(Some(_)) => false,
+                Ok(None) => {
+                    warn!("Backup not found in {path:?}, can't check whether database is stale.");
+                    false
+                },
+                Err(e) => {
+                    error!("Fingerprinting backup: {e}");
+                    return None;
+                },
+            }
+        },
+        Err(e) => {
+            error!("Reading database: {e}");
+            return None;
+        },
+    };
+
+    if rebuild {
+        if let Err(e) = amardiscord::db::build(Some(path), config.import.clone()).await {
+            error!("Building database: {e}");
+            return None;
+        }
+    }
+
+    Some(config)
+}
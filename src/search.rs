@@ -24,6 +24,7 @@ impl SearchResult {
                 avatar: row.get(2)?,
                 sent_at: row.get(3)?,
                 rowid: row.get(5)?,
+                redacted: false,
             },
         })
     }
@@ -59,17 +60,22 @@ impl SearchQuery {
         )
         .map_err(db::Error::SearchQueryBuild)?;
 
-        // If the search query has a username defined, add a clause for it.
-        if let Some(username) = self.username.map(fts_query) {
-            writeln!(query, r#"messages_fts.username MATCH ?{} OR"#, params.len() + 1)
-                .map_err(db::Error::SearchQueryBuild)?;
-            params.push(format!("*\"{username}\"*"));
+        let mut clauses = vec![];
+
+        // If the search query has a username defined, narrow the results down
+        // to its author.
+        if let Some(username) = self.username.map(fts_query).filter(|s| !s.is_empty()) {
+            params.push(username);
+            clauses.push(format!("messages_fts.username MATCH ?{}", params.len()));
         }
 
-        // Unconditionally add a clause for content.
-        writeln!(query, r#"messages_fts.content MATCH ?{}"#, params.len() + 1)
-            .map_err(db::Error::SearchQueryBuild)?;
-        params.push(fts_query(self.content));
+        let content = fts_query(self.content);
+        if !content.is_empty() || clauses.is_empty() {
+            params.push(content);
+            clauses.push(format!("messages_fts.content MATCH ?{}", params.len()));
+        }
+
+        writeln!(query, "{}", clauses.join(" AND ")).map_err(db::Error::SearchQueryBuild)?;
 
         // Order by message date.
         writeln!(query, r#"ORDER BY messages.sent_at DESC;"#)
@@ -78,6 +84,22 @@ impl SearchQuery {
         Ok((query, params))
     }
 
+    /// The full-text query searching the annotations of messages, unless the
+    /// search is narrowed down to a user.
+    pub fn annotations_query(&self) -> Option<String> {
+        let content = fts_query(&self.content);
+        (self.username.as_ref().is_none_or(String::is_empty) && !content.is_empty())
+            .then_some(content)
+    }
+
+    /// Describes the search, e.g. as the title of its feed.
+    pub fn describe(&self) -> String {
+        match self.username.as_deref().filter(|username| !username.is_empty()) {
+            Some(username) => format!("Search for \"{}\" by {username}", self.content),
+            None => format!("Search for \"{}\"", self.content),
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.content.is_empty() && self.username.as_ref().is_none_or(String::is_empty)
     }
@@ -112,6 +134,28 @@ fn fts_query<S: AsRef<str>>(input: S) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_username_query() {
+        let query =
+            SearchQuery { username: Some("Shy Lynx #9062".to_string()), content: String::new() };
+        let (query, params) = query.build().unwrap();
+
+        assert!(query.contains("messages_fts.username MATCH ?1\n"));
+        assert_eq!(params, ["\"shy\" AND \"lynx\" AND \"9062\""]);
+    }
+
+    #[test]
+    fn test_annotations_query() {
+        let query = SearchQuery { username: None, content: "Banned route".to_string() };
+        assert_eq!(query.annotations_query().unwrap(), "\"banned\" AND \"route\"");
+
+        let query =
+            SearchQuery { username: Some("Shy Lynx".to_string()), content: "route".to_string() };
+        assert!(query.annotations_query().is_none());
+        let query = SearchQuery { username: None, content: "***".to_string() };
+        assert!(query.annotations_query().is_none());
+    }
+
     #[test]
     fn test_fts_query() {
         assert_eq!(
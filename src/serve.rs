@@ -1,28 +1,72 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::extract::{Path as ExtractPath, Query as ExtractQuery, State};
-use axum::http::{header, HeaderMap};
-use axum::response::{Html, IntoResponse, Response};
-use axum::routing::get;
-use axum::Router;
+use axum::body::Body;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{
+    FromRef, FromRequestParts, Path as ExtractPath, Query as ExtractQuery, RawQuery, Request, State,
+};
+use axum::handler::HandlerWithoutStateExt;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use futures_util::stream;
+use itertools::Itertools;
+use metrics::{gauge, histogram};
+use metrics_exporter_prometheus::{BuildError, PrometheusHandle};
 use serde::Deserialize;
 use thiserror::Error;
 use tokio::net::TcpListener;
-use tokio::task;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::{signal, task};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::compression::CompressionLayer;
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::services::ServeDir;
-use tracing::info;
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+use tracing::{debug, error, info, warn};
 
+use crate::access::{self, AccessControl, Viewer};
+use crate::annotations::{Annotation, AnnotationContent, Annotations, MessageAnnotations};
+use crate::calendar::CalendarQuery;
+use crate::collections::{
+    Collection, CollectionSummary, Collections, Entry, EntryContext, NewCollection, NewEntry,
+    CONTEXT_SIZE,
+};
+use crate::config::Config;
 use crate::db::{self, Database};
-use crate::search::SearchQuery;
+use crate::export::messages::{self, ExportQuery, Format};
+use crate::feed::{Feed, FeedEntry, FEED_SIZE};
+use crate::index::{self, IndexConfig};
+use crate::links::LinksQuery;
+use crate::search::{SearchQuery, SearchResult};
+use crate::stats::{Stats, StatsQuery};
 use crate::templates::{
-    ChannelListTemplate, IndexTemplate, LayoutTemplate, MessagePageTemplate, SearchTemplate,
+    CalendarTemplate, ChannelListTemplate, CollectionTemplate, CollectionsTemplate, ErrorTemplate,
+    FeedTemplate, IndexTemplate, LayoutTemplate, Links, LinksTemplate, MessagePageTemplate,
+    SearchTemplate, StatsTemplate, UserTemplate,
 };
-use crate::ScrollDirection;
+use crate::user::UserQuery;
+use crate::{annotations, anonymise, collections, telemetry, Message, ScrollDirection};
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("serve")]
     Axum(std::io::Error),
+    #[error("opening database: {0}")]
+    OpenDatabase(db::Error),
+    #[error("installing metrics recorder: {0}")]
+    Metrics(BuildError),
     #[error("join")]
     Join(task::JoinError),
     #[error("retrieving channel list")]
@@ -31,21 +75,266 @@ pub enum Error {
     GetPage(db::Error),
     #[error("retrieving page offsets")]
     GoToMessage(db::Error),
+    #[error("finding message")]
+    FindMessage(db::Error),
     #[error("retrieving search results")]
     GetSearch(db::Error),
     #[error("retrieving channel")]
     GetChannel(db::Error),
+    #[error("retrieving calendar")]
+    GetCalendar(db::Error),
+    #[error("retrieving links")]
+    GetLinks(db::Error),
+    #[error("retrieving statistics")]
+    GetStats(db::Error),
+    #[error("retrieving user")]
+    GetUser(db::Error),
+    #[error("retrieving archive summary")]
+    GetSummary(db::Error),
+    #[error("managing annotations: {0}")]
+    Annotations(annotations::Error),
+    #[error("annotations are disabled")]
+    AnnotationsDisabled,
+    #[error("annotation {0} doesn't exist")]
+    UnknownAnnotation(u64),
+    #[error("the viewer can't annotate messages")]
+    NotAnnotationEditor,
+    #[error("the viewer can't read the metrics")]
+    NotMetricsReader,
+    #[error("managing collections: {0}")]
+    Collections(collections::Error),
+    #[error("collections are disabled")]
+    CollectionsDisabled,
+    #[error("collection {0:?} doesn't exist")]
+    UnknownCollection(String),
+    #[error("collection {0:?} is owned by another user")]
+    NotCollectionOwner(String),
+    #[error("configuring access control: {0}")]
+    AccessControl(access::Error),
+    #[error("authentication failed: {0}")]
+    Unauthorized(access::Error),
+    #[error("login required")]
+    LoginRequired,
+    #[error("channel {0} is hidden from the viewer")]
+    HiddenChannel(u64),
+    #[error("message {0} is hidden from the viewer")]
+    HiddenMessage(u64),
+    #[error("user {0:?} has no message visible to the viewer")]
+    UnknownUser(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("not found")]
+    NotFound,
+}
+
+impl Error {
+    fn status(&self) -> StatusCode {
+        match self {
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Collections(collections::Error::Invalid(_))
+            | Error::Annotations(annotations::Error::Invalid(_)) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) | Error::LoginRequired => StatusCode::UNAUTHORIZED,
+            Error::NotCollectionOwner(_) | Error::NotAnnotationEditor | Error::NotMetricsReader => {
+                StatusCode::FORBIDDEN
+            },
+            Error::CollectionsDisabled | Error::AnnotationsDisabled => {
+                StatusCode::SERVICE_UNAVAILABLE
+            },
+            // Hidden channels are indistinguishable from missing ones.
+            Error::NotFound
+            | Error::HiddenChannel(_)
+            | Error::HiddenMessage(_)
+            | Error::UnknownUser(_)
+            | Error::UnknownCollection(_)
+            | Error::UnknownAnnotation(_) => StatusCode::NOT_FOUND,
+            Error::GetChannel(e) | Error::GoToMessage(e) | Error::FindMessage(e)
+                if e.is_not_found() =>
+            {
+                StatusCode::NOT_FOUND
+            },
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // The message shown to visitors. Internal details are only logged.
+    fn public_message(&self) -> String {
+        match self {
+            Error::BadRequest(reason) => reason.clone(),
+            Error::Unauthorized(_) => "Invalid user name or password.".to_string(),
+            Error::LoginRequired => {
+                "Log in to view restricted channels and to keep collections.".to_string()
+            },
+            Error::Collections(collections::Error::Invalid(reason))
+            | Error::Annotations(annotations::Error::Invalid(reason)) => reason.clone(),
+            Error::AnnotationsDisabled => "Annotations are disabled on this server.".to_string(),
+            Error::UnknownAnnotation(_) => "This annotation doesn't exist.".to_string(),
+            Error::NotAnnotationEditor => "Only editors can annotate messages.".to_string(),
+            Error::NotMetricsReader => "You can't read the metrics of this server.".to_string(),
+            Error::CollectionsDisabled => "Collections are disabled on this server.".to_string(),
+            Error::UnknownCollection(_) => "This collection doesn't exist.".to_string(),
+            Error::NotCollectionOwner(_) => {
+                "Only the owner of this collection can change it.".to_string()
+            },
+            Error::HiddenChannel(_) => "This channel doesn't exist.".to_string(),
+            Error::GetChannel(e) if e.is_not_found() => "This channel doesn't exist.".to_string(),
+            Error::HiddenMessage(_) => "This message doesn't exist.".to_string(),
+            Error::GoToMessage(e) | Error::FindMessage(e) if e.is_not_found() => {
+                "This message doesn't exist.".to_string()
+            },
+            Error::UnknownUser(_) => "This user doesn't exist.".to_string(),
+            Error::NotFound => "This page doesn't exist.".to_string(),
+            _ => "Something went wrong while loading this page.".to_string(),
+        }
+    }
+}
+
+impl From<PathRejection> for Error {
+    fn from(rejection: PathRejection) -> Self {
+        Error::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(rejection: QueryRejection) -> Self {
+        Error::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        Error::BadRequest(rejection.body_text())
+    }
+}
+
+/// Rendered error page, attached to error responses so that [`error_pages`]
+/// can wrap it in the layout for full-page requests.
+#[derive(Clone)]
+struct ErrorPage {
+    title: String,
+    content: String,
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        Html(format!("{self:?}")).into_response()
+        let status = self.status();
+
+        if status.is_server_error() {
+            error!("{self}: {self:?}");
+        } else {
+            debug!("{self}: {self:?}");
+        }
+
+        let title = status.canonical_reason().unwrap_or("Error").to_string();
+        let content = ErrorTemplate::render(status.as_u16(), &title, &self.public_message());
+
+        let mut response = (status, Html(content.clone())).into_response();
+        response.extensions_mut().insert(ErrorPage { title, content });
+
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="amardiscord", charset="UTF-8""#),
+            );
+        }
+
+        response
     }
 }
 
+// Wraps error pages in the layout, unless they were requested by HTMX.
+async fn error_pages(request: Request, next: Next) -> Response {
+    let headers = request.headers().clone();
+    let mut response = next.run(request).await;
+
+    if let Some(ErrorPage { title, content }) = response.extensions_mut().remove::<ErrorPage>() {
+        let (mut parts, _) = response.into_parts();
+        parts.headers.insert("X-Error-Page", HeaderValue::from_static("true"));
+        return Response::from_parts(parts, Body::from(wrap_partial(&headers, title, content)));
+    }
+
+    response
+}
+
+async fn not_found() -> Error {
+    Error::NotFound
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub async fn serve() -> Result<()> {
+/// Options for [`serve`].
+pub struct Options {
+    /// Open the archive in SQLite's immutable mode.
+    pub immutable: bool,
+    /// Time after which requests are aborted.
+    pub request_timeout: Duration,
+    /// Maximum number of requests handled at the same time. Further requests
+    /// wait for one of them to complete.
+    pub max_concurrent_requests: usize,
+    /// Maximum number of channel exports streamed at the same time. Further
+    /// exports wait for one of them to complete, within the request timeout.
+    pub max_concurrent_exports: usize,
+    /// Time after which exports are aborted if the client stops reading them.
+    pub export_send_timeout: Duration,
+    pub config: Config,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            immutable: false,
+            request_timeout: Duration::from_secs(30),
+            max_concurrent_requests: 256,
+            max_concurrent_exports: 4,
+            export_send_timeout: Duration::from_secs(30),
+            config: Config::default(),
+        }
+    }
+}
+
+/// Maximum size of request bodies.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Clone)]
+struct AppState {
+    db: Arc<Database>,
+    access: Arc<AccessControl>,
+    /// `None` if the database of collections couldn't be opened.
+    collections: Option<Arc<Collections>>,
+    /// `None` if the database of annotations couldn't be opened.
+    annotations: Option<Arc<Annotations>>,
+    /// Users who can annotate messages.
+    annotation_editors: Arc<Vec<String>>,
+    index: Arc<IndexConfig>,
+    /// Users who can read the metrics.
+    metrics_readers: Arc<Vec<String>>,
+    metrics: PrometheusHandle,
+    /// Permits to stream channel exports, which outlive the request timeout
+    /// and hold a connection to the archive until they complete.
+    exports: Arc<Semaphore>,
+    export_send_timeout: Duration,
+}
+
+impl FromRef<AppState> for Arc<Database> {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<IndexConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.index.clone()
+    }
+}
+
+impl FromRequestParts<AppState> for Viewer {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self> {
+        state.access.authenticate(&parts.headers).await.map_err(Error::Unauthorized)
+    }
+}
+
+pub async fn serve(options: Options) -> Result<()> {
     macro_rules! static_get {
         ($e:literal, $content_type:literal) => {
             get(|| async { ([(header::CONTENT_TYPE, $content_type)], include_str!($e)) })
@@ -53,33 +342,156 @@ pub async fn serve() -> Result<()> {
     }
 
     info!("Loading content...");
-    let state = Arc::new(Database::new().unwrap());
+    let db = Arc::new(Database::new(options.immutable).map_err(Error::OpenDatabase)?);
+    let channels = db.get_channel_list().map_err(Error::GetChannelList)?;
+    let access = AccessControl::new(&options.config.auth, &options.config.access, &channels)
+        .map_err(Error::AccessControl)?;
+    if let Some(name) = &options.config.index.default_channel {
+        let mut entries = channels.categories.iter().flat_map(|category| &category.channels);
+        if !entries.any(|channel| &channel.name == name) {
+            warn!("The default channel {name:?} doesn't exist, the first channel is shown instead");
+        }
+    }
+    let path = &options.config.collections.path;
+    let collections = if options.config.collections.enabled {
+        match Collections::open(path) {
+            Ok(collections) => Some(Arc::new(collections)),
+            Err(e) => {
+                warn!("Collections are disabled, as {path:?} can't be opened: {e}");
+                None
+            },
+        }
+    } else {
+        None
+    };
+    // Without editors, there are no annotations to show.
+    let path = &options.config.annotations.path;
+    let annotations = if options.config.annotations.editors.is_empty() {
+        None
+    } else {
+        match Annotations::open(path) {
+            Ok(annotations) => Some(Arc::new(annotations)),
+            Err(e) => {
+                warn!("Annotations are disabled, as {path:?} can't be opened: {e}");
+                None
+            },
+        }
+    };
+    let state = AppState {
+        db,
+        access: Arc::new(access),
+        collections,
+        annotations,
+        annotation_editors: Arc::new(options.config.annotations.editors),
+        index: Arc::new(options.config.index),
+        metrics_readers: Arc::new(options.config.metrics.allow),
+        metrics: telemetry::install_recorder().map_err(Error::Metrics)?,
+        exports: Arc::new(Semaphore::new(options.max_concurrent_exports)),
+        export_send_timeout: options.export_send_timeout,
+    };
 
     info!("Starting app on http://0.0.0.0:3000");
 
     let app = Router::new()
-        .route("/", get(|| async { Html(IndexTemplate::render()) }))
+        .route("/", get(index))
         .route("/channels", get(channel_list))
         .route("/channel/{channel}/{page}", get(channel))
+        .route("/channel/{channel}/export.{format}", get(export_channel))
+        .route("/channel/{channel}/calendar", get(calendar))
+        .route("/channel/{channel}/feed.atom", get(channel_feed))
         .route("/message/{rowid}", get(message_page))
         .route("/search", get(search))
+        .route("/search/feed.atom", get(search_feed))
+        .route("/links", get(links))
+        .route("/stats", get(stats))
+        .route("/stats.json", get(stats_json))
+        .route("/user/{name}", get(user))
+        .route("/user/{name}/feed.atom", get(user_feed))
+        .route("/collections", get(collections_page))
+        .route("/collection/{id}", get(collection_page))
+        .route("/api/collections", get(api_list_collections).post(api_create_collection))
+        .route("/api/collections/{id}", get(api_get_collection).delete(api_delete_collection))
+        .route("/api/collections/{id}/entries", post(api_add_entry))
+        .route("/api/collections/{id}/entries/{entry}", delete(api_remove_entry))
+        .route(
+            "/api/messages/{rowid}/annotations",
+            get(api_list_annotations).post(api_create_annotation),
+        )
+        .route("/api/annotations/{id}", put(api_update_annotation).delete(api_delete_annotation))
+        .route("/metrics", get(metrics))
+        .route("/login", get(login))
+        .route("/identicon/{file}", get(identicon))
         .route(
             "/health",
             get(|| async { ([(header::CONTENT_TYPE, "text/plain")], "FrankerZambino") }),
         );
 
     let app = if cfg!(debug_assertions) {
-        app.fallback_service(ServeDir::new("src/static"))
+        app.fallback_service(ServeDir::new("src/static").fallback(not_found.into_service()))
     } else {
         app.route("/index.css", static_get!("./static/index.css", "text/css"))
             .route("/index.js", static_get!("./static/index.js", "application/javascript"))
             .route("/htmx.min.js", static_get!("./static/htmx.min.js", "application/javascript"))
+            .fallback(not_found)
     };
 
-    let app = app.with_state(state);
+    let app = app
+        .layer(middleware::from_fn(error_pages))
+        .layer(middleware::from_fn(telemetry::track_requests))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::make_span)
+                .on_response(telemetry::on_response),
+        )
+        .layer(CompressionLayer::new())
+        .layer(RequestBodyLimitLayer::new(MAX_BODY_SIZE))
+        .layer(GlobalConcurrencyLimitLayer::new(options.max_concurrent_requests))
+        .layer(TimeoutLayer::new(options.request_timeout))
+        .with_state(state);
     let listener = TcpListener::bind("0.0.0.0:3000").await.map_err(Error::Axum)?;
 
-    axum::serve(listener, app.into_make_service()).await.map_err(Error::Axum)
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .map_err(Error::Axum)?;
+
+    info!("Server stopped.");
+
+    Ok(())
+}
+
+// Resolves when the process receives SIGINT or SIGTERM, letting in-flight
+// requests complete before the server stops.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("Listening for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(e) => {
+                error!("Listening for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutting down...");
 }
 
 #[derive(Deserialize, Default)]
@@ -94,9 +506,18 @@ where
     T: Send + 'static,
     E: Send + Into<Error> + 'static,
 {
-    match task::spawn_blocking(f).await {
-        Ok(Ok(output)) => Ok(output),
-        Ok(Err(e)) => Err(e.into()),
+    let result = task::spawn_blocking(move || {
+        let start = Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    })
+    .await;
+
+    match result {
+        Ok((result, elapsed)) => {
+            telemetry::record_db_time(elapsed);
+            result.map_err(Into::into)
+        },
         Err(e) => Err(Error::Join(e)),
     }
 }
@@ -105,24 +526,54 @@ fn wrap_partial(headers: &HeaderMap, title: String, content: String) -> String {
     if headers.get("HX-Request").is_some() {
         content
     } else {
-        LayoutTemplate::render(&title, &content)
+        LayoutTemplate::render(&title, &content, Links::Server, None)
     }
 }
 
 fn with_channel_id(channel_id: u64, content: String) -> Response {
     let mut response = Html(content).into_response();
-    response.headers_mut().insert("X-Current-Channel-Id", channel_id.to_string().parse().unwrap());
+    response.headers_mut().insert("X-Current-Channel-Id", HeaderValue::from(channel_id));
     response
 }
 
+async fn index(State(state): State<AppState>, viewer: Viewer) -> Result<Html<String>> {
+    let config = state.index.clone();
+
+    task(move || {
+        let channel_list = state.db.get_channel_list().map_err(Error::GetChannelList)?;
+        let channel_list = viewer.filter_channel_list(channel_list);
+        let channel_ids = channel_list
+            .categories
+            .iter()
+            .flat_map(|category| category.channels.iter().map(|channel| channel.id))
+            .collect_vec();
+
+        let summary = state.db.get_archive_summary(&channel_ids).map_err(Error::GetSummary)?;
+        let default_channel =
+            index::default_channel(&channel_list, state.index.default_channel.as_deref()).cloned();
+        Ok::<_, Error>((summary, default_channel))
+    })
+    .await
+    .map(|(summary, default_channel)| {
+        IndexTemplate::render(config.name.as_deref(), &summary, default_channel.as_ref())
+    })
+    .map(Html)
+}
+
 async fn channel_list(
     State(db): State<Arc<Database>>,
-    ExtractQuery(query): ExtractQuery<ChannelListQuery>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<ChannelListQuery>, QueryRejection>,
     headers: HeaderMap,
 ) -> Result<Html<String>> {
+    let ExtractQuery(query) = query?;
+
     task(move || db.get_channel_list().map_err(Error::GetChannelList))
         .await
-        .map(|channel_list| ChannelListTemplate::render(&channel_list, query.current_channel_id))
+        .map(|channel_list| viewer.filter_channel_list(channel_list))
+        .map(|channel_list| {
+            ChannelListTemplate::render(&channel_list, query.current_channel_id, Links::Server)
+        })
         .map(|content| wrap_partial(&headers, "channel_list".to_string(), content))
         .map(Html)
 }
@@ -134,28 +585,38 @@ struct PageQuery {
 }
 
 async fn channel(
-    State(db): State<Arc<Database>>,
-    ExtractPath((channel_id, page)): ExtractPath<(u64, u64)>,
-    ExtractQuery(page_query): ExtractQuery<PageQuery>,
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<(u64, u64)>, PathRejection>,
+    page_query: std::result::Result<ExtractQuery<PageQuery>, QueryRejection>,
     headers: HeaderMap,
 ) -> Result<Response> {
+    let ExtractPath((channel_id, page)) = path?;
+    let ExtractQuery(page_query) = page_query?;
+
+    if !viewer.can_view(channel_id) {
+        return Err(Error::HiddenChannel(channel_id));
+    }
+
     task(move || {
         // first get the channel
-        let channel = db.get_channel(channel_id).map_err(Error::GetChannel)?;
-        let messages = db.get_page(channel_id, page).map_err(Error::GetPage)?;
-        Ok::<_, Error>((channel.name, messages))
+        let channel = state.db.get_channel(channel_id).map_err(Error::GetChannel)?;
+        let messages = state.db.get_page(channel_id, page).map_err(Error::GetPage)?;
+        let annotations = page_annotations(&state, &viewer, &messages)?;
+        Ok::<_, Error>((channel.name, messages, annotations))
     })
     .await
-    .map(|(channel_name, messages)| {
+    .map(|(channel_name, messages, annotations)| {
         (
             channel_name.clone(),
-            MessagePageTemplate::render(
+            MessagePageTemplate::render_annotated(
                 &messages,
                 channel_id,
                 channel_name,
                 page,
                 page_query.direction,
                 None,
+                annotations,
             ),
         )
     })
@@ -163,29 +624,72 @@ async fn channel(
     .map(|content| with_channel_id(channel_id, content))
 }
 
-async fn message_page(
+async fn calendar(
     State(db): State<Arc<Database>>,
-    ExtractPath(rowid): ExtractPath<u64>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+    query: std::result::Result<ExtractQuery<CalendarQuery>, QueryRejection>,
     headers: HeaderMap,
 ) -> Result<Response> {
+    let ExtractPath(channel_id) = path?;
+    let ExtractQuery(query) = query?;
+
+    if !viewer.can_view(channel_id) {
+        return Err(Error::HiddenChannel(channel_id));
+    }
+
+    task(move || {
+        let channel = db.get_channel(channel_id).map_err(Error::GetChannel)?;
+        let years = db.get_channel_years(channel_id).map_err(Error::GetCalendar)?;
+
+        let year = query.year.or_else(|| years.last().map(|(year, _)| *year));
+        let days = match year {
+            Some(year) => db.get_channel_days(channel_id, year).map_err(Error::GetCalendar)?,
+            None => Vec::new(),
+        };
+
+        Ok::<_, Error>((channel.name, years, year, days))
+    })
+    .await
+    .map(|(channel_name, years, year, days)| {
+        let content = CalendarTemplate::render(channel_id, &channel_name, &years, year, &days);
+        wrap_partial(&headers, channel_name, content)
+    })
+    .map(|content| with_channel_id(channel_id, content))
+}
+
+async fn message_page(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let ExtractPath(rowid) = path?;
+
     task(move || {
         let (channel_id, channel_name, page) =
-            db.go_to_message(rowid).map_err(Error::GoToMessage)?;
-        let messages = db.get_page(channel_id, page).map_err(Error::GetPage)?;
-        Ok::<_, Error>((channel_id, channel_name, page, messages))
+            state.db.go_to_message(rowid).map_err(Error::GoToMessage)?;
+        if !viewer.can_view(channel_id) {
+            return Err(Error::HiddenMessage(rowid));
+        }
+
+        let messages = state.db.get_page(channel_id, page).map_err(Error::GetPage)?;
+        let annotations = page_annotations(&state, &viewer, &messages)?;
+        Ok::<_, Error>((channel_id, channel_name, page, messages, annotations))
     })
     .await
-    .map(|(channel_id, channel_name, page, messages)| {
+    .map(|(channel_id, channel_name, page, messages, annotations)| {
         (
             channel_id,
             channel_name.clone(),
-            MessagePageTemplate::render(
+            MessagePageTemplate::render_annotated(
                 &messages,
                 channel_id,
                 channel_name.clone(),
                 page,
                 ScrollDirection::Both,
                 Some(rowid),
+                annotations,
             ),
         )
     })
@@ -196,20 +700,787 @@ async fn message_page(
 }
 
 async fn search(
-    State(db): State<Arc<Database>>,
-    ExtractQuery(query): ExtractQuery<SearchQuery>,
+    State(state): State<AppState>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<SearchQuery>, QueryRejection>,
     headers: HeaderMap,
 ) -> Result<Response> {
+    let ExtractQuery(query) = query?;
+
     if query.is_empty() {
         // Trigger a refresh if query is empty
         let mut headers = HeaderMap::new();
-        headers.insert("HX-Refresh", "true".parse().unwrap());
+        headers.insert("HX-Refresh", HeaderValue::from_static("true"));
         return Ok((headers, Html(String::new())).into_response());
     }
 
-    task(move || db.get_search(query).map_err(Error::GetSearch))
+    task(move || {
+        let results = find_messages(&state, &viewer, query)?;
+        let rowids = results.iter().map(|result| result.message_rowid).collect_vec();
+        let annotations = message_annotations(&state, &rowids, false)?;
+
+        Ok::<_, Error>((results, annotations))
+    })
+    .await
+    .map(|(search_results, annotations)| SearchTemplate::render(&search_results, &annotations))
+    .map(|content| wrap_partial(&headers, "Search".to_string(), content))
+    .map(|content| Html(content).into_response())
+}
+
+// Searches the messages the viewer can view, the latest first.
+fn find_messages(
+    state: &AppState,
+    viewer: &Viewer,
+    query: SearchQuery,
+) -> Result<Vec<SearchResult>> {
+    let start = Instant::now();
+    let annotations_query = query.annotations_query();
+    let mut results = state.db.get_search(query).map_err(Error::GetSearch)?;
+
+    // Messages are found by their annotations as well.
+    if let (Some(annotations), Some(query)) = (&state.annotations, annotations_query) {
+        let keys = annotations.search(&query).map_err(Error::Annotations)?;
+        let found = results.iter().map(|result| result.message_rowid).collect::<HashSet<_>>();
+
+        let mut rowids = Vec::new();
+        for key in keys {
+            match state.db.find_message(&key) {
+                Ok(rowid) if !found.contains(&rowid) => rowids.push(rowid),
+                Ok(_) => {},
+                // The message isn't in this build of the archive.
+                Err(e) if e.is_not_found() => {},
+                Err(e) => return Err(Error::FindMessage(e)),
+            }
+        }
+
+        results.extend(state.db.get_search_results(&rowids).map_err(Error::GetSearch)?);
+        results.sort_by_key(|result| Reverse(result.message.sent_at));
+    }
+    histogram!("amardiscord_search_duration_seconds").record(start.elapsed());
+
+    results.retain(|result| viewer.can_view(result.channel_id));
+    Ok(results)
+}
+
+async fn links(
+    State(db): State<Arc<Database>>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<LinksQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Html<String>> {
+    let ExtractQuery(query) = query?;
+
+    let page = {
+        let query = query.clone();
+        task(move || {
+            let channel_ids = visible_channel_ids(&db, &viewer)?;
+            if let Some(channel_id) = query.channel.filter(|id| !channel_ids.contains(id)) {
+                return Err(Error::HiddenChannel(channel_id));
+            }
+
+            db.get_links(&query, &channel_ids).map_err(Error::GetLinks)
+        })
+        .await?
+    };
+
+    let content = LinksTemplate::render(&page, &query);
+    Ok(Html(wrap_partial(&headers, "Links".to_string(), content)))
+}
+
+// Lists the ids of the channels the viewer can view.
+fn visible_channel_ids(db: &Database, viewer: &Viewer) -> Result<Vec<u64>> {
+    let channel_list = db.get_channel_list().map_err(Error::GetChannelList)?;
+
+    Ok(viewer
+        .filter_channel_list(channel_list)
+        .categories
+        .iter()
+        .flat_map(|category| category.channels.iter().map(|channel| channel.id))
+        .collect())
+}
+
+// Maps the ids of the channels the viewer can view to their names.
+fn visible_channel_names(db: &Database, viewer: &Viewer) -> Result<HashMap<u64, String>> {
+    let channel_list = db.get_channel_list().map_err(Error::GetChannelList)?;
+
+    Ok(viewer
+        .filter_channel_list(channel_list)
+        .categories
+        .into_iter()
+        .flat_map(|category| category.channels)
+        .map(|channel| (channel.id, channel.name))
+        .collect())
+}
+
+// Computes the statistics of the channels the viewer can view. Statistics of a
+// hidden channel are indistinguishable from those of a missing one.
+async fn get_stats(db: Arc<Database>, viewer: Viewer, query: StatsQuery) -> Result<Stats> {
+    task(move || {
+        let channel_ids = visible_channel_ids(&db, &viewer)?;
+
+        if let Some(channel_id) = query.channel.filter(|id| !channel_ids.contains(id)) {
+            return Err(Error::HiddenChannel(channel_id));
+        }
+
+        db.get_stats(&query, &channel_ids).map_err(Error::GetStats)
+    })
+    .await
+}
+
+async fn stats(
+    State(db): State<Arc<Database>>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<StatsQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Html<String>> {
+    let ExtractQuery(query) = query?;
+
+    get_stats(db, viewer, query.clone())
         .await
-        .map(|search_results| SearchTemplate::render(&search_results))
-        .map(|content| wrap_partial(&headers, "Search".to_string(), content))
-        .map(|content| Html(content).into_response())
+        .map(|stats| StatsTemplate::render(&stats, &query))
+        .map(|content| wrap_partial(&headers, "Statistics".to_string(), content))
+        .map(Html)
+}
+
+async fn stats_json(
+    State(db): State<Arc<Database>>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<StatsQuery>, QueryRejection>,
+) -> Result<Json<Stats>> {
+    let ExtractQuery(query) = query?;
+
+    get_stats(db, viewer, query).await.map(Json)
+}
+
+async fn user(
+    State(db): State<Arc<Database>>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+    query: std::result::Result<ExtractQuery<UserQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Html<String>> {
+    let ExtractPath(username) = path?;
+    let ExtractQuery(query) = query?;
+
+    task(move || {
+        let channel_ids = visible_channel_ids(&db, &viewer)?;
+        let summary = db
+            .get_user(&username, &channel_ids)
+            .map_err(Error::GetUser)?
+            .ok_or_else(|| Error::UnknownUser(username.clone()))?;
+        let messages =
+            db.get_user_page(&username, query.page, &channel_ids).map_err(Error::GetUser)?;
+        Ok::<_, Error>((summary, messages))
+    })
+    .await
+    .map(|(summary, messages)| {
+        let content = UserTemplate::render(&summary, &messages, query.page);
+        wrap_partial(&headers, summary.username, content)
+    })
+    .map(Html)
+}
+
+fn feed_response(feed: &Feed, index: &IndexConfig) -> Response {
+    let content = FeedTemplate::render(feed, index.base_url.as_deref());
+    ([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], content).into_response()
+}
+
+async fn channel_feed(
+    State(db): State<Arc<Database>>,
+    State(index): State<Arc<IndexConfig>>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+) -> Result<Response> {
+    let ExtractPath(channel_id) = path?;
+
+    if !viewer.can_view(channel_id) {
+        return Err(Error::HiddenChannel(channel_id));
+    }
+
+    task(move || {
+        let channel = db.get_channel(channel_id).map_err(Error::GetChannel)?;
+        // The first page holds the latest messages.
+        let messages = db.get_page(channel_id, 0).map_err(Error::GetPage)?;
+
+        let entries = messages
+            .into_iter()
+            .filter(|message| !message.redacted)
+            .take(FEED_SIZE)
+            .map(|message| FeedEntry { channel_name: channel.name.clone(), message })
+            .collect();
+
+        Ok::<_, Error>(Feed {
+            title: format!("#{}", channel.name),
+            path: format!("/channel/{channel_id}/feed.atom"),
+            page: format!("/channel/{channel_id}/0"),
+            entries,
+        })
+    })
+    .await
+    .map(|feed| feed_response(&feed, &index))
+}
+
+async fn user_feed(
+    State(db): State<Arc<Database>>,
+    State(index): State<Arc<IndexConfig>>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+) -> Result<Response> {
+    let ExtractPath(username) = path?;
+
+    task(move || {
+        let channel_ids = visible_channel_ids(&db, &viewer)?;
+        if db.get_user(&username, &channel_ids).map_err(Error::GetUser)?.is_none() {
+            return Err(Error::UnknownUser(username));
+        }
+        let messages = db.get_user_page(&username, 0, &channel_ids).map_err(Error::GetUser)?;
+
+        let entries = messages
+            .into_iter()
+            .take(FEED_SIZE)
+            .map(|message| FeedEntry {
+                channel_name: message.channel_name,
+                message: message.message,
+            })
+            .collect();
+
+        let page = Links::Server.user(&username);
+        Ok(Feed { title: username, path: format!("{page}/feed.atom"), page, entries })
+    })
+    .await
+    .map(|feed| feed_response(&feed, &index))
+}
+
+async fn search_feed(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    query: std::result::Result<ExtractQuery<SearchQuery>, QueryRejection>,
+    RawQuery(raw_query): RawQuery,
+) -> Result<Response> {
+    let ExtractQuery(query) = query?;
+    let raw_query = raw_query.unwrap_or_default();
+    let index = state.index.clone();
+
+    task(move || {
+        let title = query.describe();
+        let results = find_messages(&state, &viewer, query)?;
+        let channel_names = visible_channel_names(&state.db, &viewer)?;
+
+        let entries = results
+            .into_iter()
+            .take(FEED_SIZE)
+            .map(|result| FeedEntry {
+                channel_name: channel_names.get(&result.channel_id).cloned().unwrap_or_default(),
+                message: result.message,
+            })
+            .collect();
+
+        Ok::<_, Error>(Feed {
+            title,
+            path: format!("/search/feed.atom?{raw_query}"),
+            page: format!("/search?{raw_query}"),
+            entries,
+        })
+    })
+    .await
+    .map(|feed| feed_response(&feed, &index))
+}
+
+// The collections, unless they couldn't be opened.
+fn enabled_collections(state: &AppState) -> Result<Arc<Collections>> {
+    state.collections.clone().ok_or(Error::CollectionsDisabled)
+}
+
+// Retrieves a collection. If it's retrieved to be changed by `editor`, they
+// must own it.
+fn get_collection(
+    collections: &Collections,
+    id: &str,
+    editor: Option<&Viewer>,
+) -> Result<Collection> {
+    let collection = collections
+        .get(id)
+        .map_err(Error::Collections)?
+        .ok_or_else(|| Error::UnknownCollection(id.to_string()))?;
+
+    if let Some(viewer) = editor {
+        let name = viewer.name.as_ref().ok_or(Error::LoginRequired)?;
+        if *name != collection.owner {
+            return Err(Error::NotCollectionOwner(id.to_string()));
+        }
+    }
+
+    Ok(collection)
+}
+
+// Retrieves the messages of a collection around its entries, leaving out those
+// hidden from the viewer or removed from the archive. Returns the ids of the
+// entries left out.
+fn collection_entries(
+    db: &Database,
+    viewer: &Viewer,
+    entries: Vec<Entry>,
+) -> Result<(Vec<EntryContext>, Vec<u64>)> {
+    let mut contexts = Vec::new();
+    let mut hidden = Vec::new();
+
+    for entry in entries {
+        let message = match db.find_message(&entry.message) {
+            Ok(message) => message,
+            // The message isn't in this build of the archive.
+            Err(e) if e.is_not_found() => {
+                hidden.push(entry.id);
+                continue;
+            },
+            Err(e) => return Err(Error::FindMessage(e)),
+        };
+
+        match db.get_message_context(message, CONTEXT_SIZE) {
+            Ok((channel_id, channel_name, messages)) if viewer.can_view(channel_id) => {
+                let context = EntryContext { entry, message, channel_id, channel_name, messages };
+                contexts.push(context);
+            },
+            Ok(_) => hidden.push(entry.id),
+            Err(e) if e.is_not_found() => hidden.push(entry.id),
+            Err(e) => return Err(Error::GoToMessage(e)),
+        }
+    }
+
+    Ok((contexts, hidden))
+}
+
+async fn collections_page(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    headers: HeaderMap,
+) -> Result<Html<String>> {
+    let collections = enabled_collections(&state)?;
+    let owner = viewer.name.ok_or(Error::LoginRequired)?;
+
+    let list = task(move || collections.list(&owner).map_err(Error::Collections)).await?;
+
+    let content = CollectionsTemplate::render(&list);
+    Ok(Html(wrap_partial(&headers, "Collections".to_string(), content)))
+}
+
+async fn collection_page(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+    headers: HeaderMap,
+) -> Result<Html<String>> {
+    let ExtractPath(id) = path?;
+    let collections = enabled_collections(&state)?;
+
+    let (collection, entries, hidden, viewer) = task(move || {
+        let mut collection = get_collection(&collections, &id, None)?;
+        let entries = std::mem::take(&mut collection.entries);
+        let (entries, hidden) = collection_entries(&state.db, &viewer, entries)?;
+        Ok::<_, Error>((collection, entries, hidden, viewer))
+    })
+    .await?;
+
+    let is_owner = viewer.name.as_ref() == Some(&collection.owner);
+    let content = CollectionTemplate::render(&collection, &entries, &hidden, is_owner);
+    Ok(Html(wrap_partial(&headers, collection.name.clone(), content)))
+}
+
+async fn api_list_collections(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> Result<Json<Vec<CollectionSummary>>> {
+    let collections = enabled_collections(&state)?;
+    let owner = viewer.name.ok_or(Error::LoginRequired)?;
+
+    task(move || collections.list(&owner).map_err(Error::Collections)).await.map(Json)
+}
+
+async fn api_create_collection(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    body: std::result::Result<Json<NewCollection>, JsonRejection>,
+) -> Result<(StatusCode, Json<CollectionSummary>)> {
+    let Json(body) = body?;
+    let collections = enabled_collections(&state)?;
+    let owner = viewer.name.ok_or(Error::LoginRequired)?;
+
+    task(move || collections.create(&owner, &body.name).map_err(Error::Collections))
+        .await
+        .map(|collection| (StatusCode::CREATED, Json(collection)))
+}
+
+async fn api_get_collection(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+) -> Result<Json<Collection>> {
+    let ExtractPath(id) = path?;
+    let collections = enabled_collections(&state)?;
+
+    task(move || {
+        let mut collection = get_collection(&collections, &id, None)?;
+        let is_owner = viewer.name.as_ref() == Some(&collection.owner);
+        // Only the messages the viewer can view are given away.
+        let mut entries = Vec::new();
+        for entry in std::mem::take(&mut collection.entries) {
+            let channel_id = state
+                .db
+                .find_message(&entry.message)
+                .and_then(|message| state.db.go_to_message(message))
+                .map(|(channel_id, ..)| channel_id);
+            match channel_id {
+                Ok(channel_id) if viewer.can_view(channel_id) => entries.push(entry),
+                Ok(_) if is_owner => collection.hidden.push(entry.id),
+                Ok(_) => {},
+                Err(e) if e.is_not_found() && is_owner => collection.hidden.push(entry.id),
+                Err(e) if e.is_not_found() => {},
+                Err(e) => return Err(Error::GoToMessage(e)),
+            }
+        }
+        collection.entries = entries;
+        Ok(collection)
+    })
+    .await
+    .map(Json)
+}
+
+async fn api_delete_collection(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+) -> Result<StatusCode> {
+    let ExtractPath(id) = path?;
+    let collections = enabled_collections(&state)?;
+
+    task(move || {
+        get_collection(&collections, &id, Some(&viewer))?;
+        collections.delete(&id).map_err(Error::Collections)
+    })
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+async fn api_add_entry(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<String>, PathRejection>,
+    body: std::result::Result<Json<NewEntry>, JsonRejection>,
+) -> Result<Json<Entry>> {
+    let ExtractPath(id) = path?;
+    let Json(body) = body?;
+    let collections = enabled_collections(&state)?;
+
+    task(move || {
+        get_collection(&collections, &id, Some(&viewer))?;
+
+        visible_message(&state.db, &viewer, body.message)?;
+        let key = state.db.get_message_key(body.message).map_err(Error::FindMessage)?;
+
+        collections.add_entry(&id, key, body.note.as_deref()).map_err(Error::Collections)
+    })
+    .await
+    .map(Json)
+}
+
+async fn api_remove_entry(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<(String, u64)>, PathRejection>,
+) -> Result<StatusCode> {
+    let ExtractPath((id, entry)) = path?;
+    let collections = enabled_collections(&state)?;
+
+    task(move || {
+        get_collection(&collections, &id, Some(&viewer))?;
+        collections.remove_entry(&id, entry).map_err(Error::Collections)
+    })
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+// Retrieves the annotations of some messages, which `editable` tells whether
+// the viewer can change.
+fn message_annotations(
+    state: &AppState,
+    rowids: &[u64],
+    editable: bool,
+) -> Result<MessageAnnotations> {
+    let Some(annotations) = &state.annotations else {
+        return Ok(MessageAnnotations::default());
+    };
+
+    // Annotations refer to messages by their keys, which survive rebuilds.
+    let keys = state.db.get_message_keys(rowids).map_err(Error::FindMessage)?;
+    let (rowids, keys): (HashMap<_, _>, Vec<_>) =
+        keys.into_iter().map(|(rowid, key)| ((key.clone(), rowid), key)).unzip();
+
+    let annotations = annotations.for_messages(&keys).map_err(Error::Annotations)?;
+    Ok(MessageAnnotations::new(annotations, &rowids, editable))
+}
+
+// Retrieves the annotations of a page of messages.
+fn page_annotations(
+    state: &AppState,
+    viewer: &Viewer,
+    messages: &[Message],
+) -> Result<MessageAnnotations> {
+    let rowids = messages.iter().map(|message| message.rowid).collect_vec();
+    message_annotations(state, &rowids, can_annotate(state, viewer))
+}
+
+fn can_annotate(state: &AppState, viewer: &Viewer) -> bool {
+    state.annotations.is_some() && state.access.allows(&state.annotation_editors, viewer)
+}
+
+// The annotations, unless they couldn't be opened, if the viewer can change
+// them.
+fn editable_annotations(state: &AppState, viewer: &Viewer) -> Result<Arc<Annotations>> {
+    let annotations = state.annotations.clone().ok_or(Error::AnnotationsDisabled)?;
+
+    if viewer.name.is_none() {
+        Err(Error::LoginRequired)
+    } else if !state.access.allows(&state.annotation_editors, viewer) {
+        Err(Error::NotAnnotationEditor)
+    } else {
+        Ok(annotations)
+    }
+}
+
+// Checks that the viewer can view a message, returning its channel.
+fn visible_message(db: &Database, viewer: &Viewer, rowid: u64) -> Result<u64> {
+    let (channel_id, ..) = db.go_to_message(rowid).map_err(Error::GoToMessage)?;
+    if !viewer.can_view(channel_id) {
+        return Err(Error::HiddenMessage(rowid));
+    }
+
+    Ok(channel_id)
+}
+
+// Finds the message of an annotation in this build of the archive, checking
+// that the viewer can view it.
+fn annotated_message(db: &Database, viewer: &Viewer, annotation: &Annotation) -> Result<u64> {
+    let rowid = db.find_message(&annotation.message).map_err(Error::FindMessage)?;
+    visible_message(db, viewer, rowid)?;
+
+    Ok(rowid)
+}
+
+async fn api_list_annotations(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+) -> Result<Json<Vec<Annotation>>> {
+    let ExtractPath(rowid) = path?;
+    let annotations = state.annotations.clone().ok_or(Error::AnnotationsDisabled)?;
+
+    task(move || {
+        visible_message(&state.db, &viewer, rowid)?;
+        let key = state.db.get_message_key(rowid).map_err(Error::FindMessage)?;
+        annotations.for_messages(&[key]).map_err(Error::Annotations)
+    })
+    .await
+    .map(Json)
+}
+
+async fn api_create_annotation(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+    body: std::result::Result<Json<AnnotationContent>, JsonRejection>,
+) -> Result<(StatusCode, Json<Annotation>)> {
+    let ExtractPath(rowid) = path?;
+    let Json(body) = body?;
+    let annotations = editable_annotations(&state, &viewer)?;
+    let author = viewer.name.clone().unwrap_or_default();
+
+    task(move || {
+        visible_message(&state.db, &viewer, rowid)?;
+        let key = state.db.get_message_key(rowid).map_err(Error::FindMessage)?;
+        annotations.create(&key, &author, &body.content).map_err(Error::Annotations)
+    })
+    .await
+    .map(|annotation| (StatusCode::CREATED, Json(annotation)))
+}
+
+async fn api_update_annotation(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+    body: std::result::Result<Json<AnnotationContent>, JsonRejection>,
+) -> Result<Json<Annotation>> {
+    let ExtractPath(id) = path?;
+    let Json(body) = body?;
+    let annotations = editable_annotations(&state, &viewer)?;
+
+    task(move || {
+        let annotation =
+            annotations.get(id).map_err(Error::Annotations)?.ok_or(Error::UnknownAnnotation(id))?;
+        annotated_message(&state.db, &viewer, &annotation)?;
+
+        annotations
+            .update(id, &body.content)
+            .map_err(Error::Annotations)?
+            .ok_or(Error::UnknownAnnotation(id))
+    })
+    .await
+    .map(Json)
+}
+
+async fn api_delete_annotation(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<u64>, PathRejection>,
+) -> Result<StatusCode> {
+    let ExtractPath(id) = path?;
+    let annotations = editable_annotations(&state, &viewer)?;
+
+    task(move || {
+        let annotation =
+            annotations.get(id).map_err(Error::Annotations)?.ok_or(Error::UnknownAnnotation(id))?;
+        annotated_message(&state.db, &viewer, &annotation)?;
+
+        annotations.delete(id).map_err(Error::Annotations)
+    })
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+/// Writes the export of a channel to the response body as it's produced,
+/// failing if the client doesn't read a chunk within `timeout`.
+struct BodyWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    timeout: Duration,
+    runtime: Handle,
+}
+
+impl std::io::Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let send = self.sender.send_timeout(buf.to_vec(), self.timeout);
+        self.runtime.block_on(send).map_err(|e| match e {
+            SendTimeoutError::Timeout(_) => std::io::ErrorKind::TimedOut.into(),
+            SendTimeoutError::Closed(_) => std::io::Error::from(std::io::ErrorKind::BrokenPipe),
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Size of the chunks of exports sent to the client.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+async fn export_channel(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    path: std::result::Result<ExtractPath<(u64, String)>, PathRejection>,
+    query: std::result::Result<ExtractQuery<ExportQuery>, QueryRejection>,
+) -> Result<Response> {
+    let ExtractPath((channel_id, format)) = path?;
+    let ExtractQuery(query) = query?;
+    let format: Format = format.parse().map_err(|_| Error::NotFound)?;
+
+    if !viewer.can_view(channel_id) {
+        return Err(Error::HiddenChannel(channel_id));
+    }
+
+    let db = state.db;
+    let channel = {
+        let db = db.clone();
+        task(move || db.get_channel(channel_id).map_err(Error::GetChannel)).await?
+    };
+
+    // Waiting for a permit is bounded by the request timeout, unlike the
+    // export itself.
+    let permit = state.exports.acquire_owned().await.expect("the semaphore is never closed");
+
+    let filename: String = channel
+        .name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect();
+    let disposition = format!("attachment; filename=\"{filename}.{}\"", format.extension());
+
+    // The export is written on a blocking thread, and streamed as it goes.
+    let (sender, mut receiver) = mpsc::channel(4);
+    let completed = Arc::new(AtomicBool::new(false));
+    let export_completed = completed.clone();
+    let body_writer = BodyWriter {
+        sender: sender.clone(),
+        timeout: state.export_send_timeout,
+        runtime: Handle::current(),
+    };
+    task::spawn_blocking(move || {
+        let _permit = permit;
+        let writer = BufWriter::with_capacity(EXPORT_CHUNK_SIZE, body_writer);
+        let filter = query.filter();
+
+        match messages::write_channel(&db, &channel, &filter, format, writer) {
+            Ok(()) => export_completed.store(true, Ordering::Release),
+            Err(e) if !sender.is_closed() => error!("Exporting channel {channel_id}: {e}"),
+            // The client went away.
+            Err(_) => {},
+        }
+    });
+
+    // Exports that didn't complete abort the response rather than sending a
+    // truncated export.
+    let body = Body::from_stream(stream::poll_fn(move |cx| {
+        receiver.poll_recv(cx).map(|chunk| match chunk {
+            Some(chunk) => Some(Ok(chunk)),
+            None if completed.load(Ordering::Acquire) => None,
+            None => Some(Err(std::io::Error::other("The export was aborted"))),
+        })
+    }));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CONTENT_DISPOSITION, &disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+// Avatar of an anonymised user. Identicons never change, so they can be cached
+// indefinitely.
+async fn identicon(ExtractPath(file): ExtractPath<String>) -> Result<impl IntoResponse> {
+    let svg = file.strip_suffix(".svg").and_then(anonymise::identicon).ok_or(Error::NotFound)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        svg,
+    ))
+}
+
+// Asks the browser for credentials, then goes back to the archive.
+async fn login(viewer: Viewer) -> Result<Redirect> {
+    match viewer.name {
+        Some(_) => Ok(Redirect::to("/")),
+        None => Err(Error::LoginRequired),
+    }
+}
+
+async fn metrics(State(state): State<AppState>, viewer: Viewer) -> Result<impl IntoResponse> {
+    if !state.access.allows(&state.metrics_readers, &viewer) {
+        return Err(if viewer.name.is_none() {
+            Error::LoginRequired
+        } else {
+            Error::NotMetricsReader
+        });
+    }
+
+    let pool = state.db.pool_state();
+    gauge!("amardiscord_db_pool_max_connections").set(pool.max_size);
+    gauge!("amardiscord_db_pool_connections").set(pool.connections);
+    gauge!("amardiscord_db_pool_idle_connections").set(pool.idle_connections);
+
+    state.metrics.run_upkeep();
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], state.metrics.render()))
 }
@@ -0,0 +1,51 @@
+//! Databases kept next to the archive, such as collections and annotations,
+//! which hold what users add while serving rather than what was imported.
+
+use std::path::Path;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("database connection pool error: {0}")]
+    Pool(#[from] r2d2::Error),
+    #[error("database error: {0}")]
+    Rusqlite(#[from] rusqlite::Error),
+    /// The request was rejected, for the reason given.
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Opens a database, creating it and its `schema` if needed. The schema must
+/// only create what doesn't exist yet.
+pub(crate) fn open(path: &Path, schema: &str) -> Result<Pool<SqliteConnectionManager>, Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|db| {
+        db.execute_batch(
+            r#"
+            PRAGMA foreign_keys = ON;
+            PRAGMA busy_timeout = 5000;
+            "#,
+        )
+    });
+    let pool = Pool::builder().max_size(8).build(manager)?;
+
+    // Readers don't block the writer in WAL mode.
+    let db = pool.get()?;
+    db.execute_batch("PRAGMA journal_mode = WAL;")?;
+    db.execute_batch(schema)?;
+
+    Ok(pool)
+}
+
+/// Creates an in-memory database with `schema`, for tests.
+#[cfg(test)]
+pub(crate) fn memory(schema: &str) -> Pool<SqliteConnectionManager> {
+    let manager = SqliteConnectionManager::memory()
+        .with_init(|db| db.execute_batch("PRAGMA foreign_keys = ON;"));
+    let pool = Pool::builder().max_size(1).build(manager).unwrap();
+    pool.get().unwrap().execute_batch(schema).unwrap();
+
+    pool
+}
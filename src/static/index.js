@@ -1,17 +1,136 @@
 (() => {
   htmx.config.scrollBehavior = "auto";
 
-  window.copyMessageLink = (el, messageId) => {
-    const url = `${window.location.origin}/message/${messageId}`;
-    navigator.clipboard.writeText(url);
-
+  // Briefly replaces the text of a button to confirm an action.
+  function flash(el, text) {
     const previousContent = el.innerHTML;
     el.classList.add("copied");
-    el.innerText = "Copied!";
+    el.innerText = text;
     setTimeout(() => {
       el.innerHTML = previousContent;
       el.classList.remove("copied");
     }, 1000);
+  }
+
+  window.copyLink = (el, path) => {
+    navigator.clipboard.writeText(`${window.location.origin}${path}`);
+    flash(el, "Copied!");
+  };
+
+  window.copyMessageLink = (el, messageId) => {
+    copyLink(el, `/message/${messageId}`);
+  };
+
+  // Calls the JSON API, returning `null` and showing the reason on
+  // failure.
+  async function callApi(method, path, body) {
+    const response = await fetch(path, {
+      method,
+      headers: body === undefined ? {} : { "Content-Type": "application/json" },
+      body: body === undefined ? undefined : JSON.stringify(body),
+    });
+
+    if (!response.ok) {
+      // Errors are reported as error pages.
+      const page = new DOMParser().parseFromString(
+        await response.text(),
+        "text/html",
+      );
+      alert(page.querySelector(".error-page p")?.textContent ?? response.statusText);
+      return null;
+    }
+
+    return response.status === 204 ? {} : response.json();
+  }
+
+  window.bookmarkMessage = async (el, messageId) => {
+    const collections = await callApi("GET", "/api/collections");
+    if (!collections) return;
+
+    const names = collections.map((collection) => collection.name);
+    const name = prompt(
+      names.length > 0
+        ? `Add the message to one of your collections (${names.join(", ")}), or to a new one:`
+        : "Name of a new collection to add the message to:",
+      names[0] ?? "",
+    )?.trim();
+    if (!name) return;
+
+    const collection =
+      collections.find((collection) => collection.name === name) ??
+      (await callApi("POST", "/api/collections", { name }));
+    if (!collection) return;
+
+    const note = prompt("Note (optional):");
+    if (note === null) return;
+
+    const entry = await callApi(
+      "POST",
+      `/api/collections/${collection.id}/entries`,
+      { message: messageId, note },
+    );
+    if (entry) {
+      flash(el, "Bookmarked!");
+    }
+  };
+
+  window.createCollection = async (evt) => {
+    evt.preventDefault();
+
+    const name = new FormData(evt.target).get("name");
+    const collection = await callApi("POST", "/api/collections", { name });
+    if (collection) {
+      window.location.assign(`/collection/${collection.id}`);
+    }
+  };
+
+  window.deleteCollection = async (collectionId) => {
+    if (!confirm("Delete this collection?")) return;
+
+    if (await callApi("DELETE", `/api/collections/${collectionId}`)) {
+      window.location.assign("/collections");
+    }
+  };
+
+  window.removeFromCollection = async (collectionId, ...entryIds) => {
+    for (const entryId of entryIds) {
+      const path = `/api/collections/${collectionId}/entries/${entryId}`;
+      if (!(await callApi("DELETE", path))) return;
+    }
+    htmx.ajax("GET", window.location.pathname, { target: "#content" });
+  };
+
+  // Shows a message again with its annotations once they've changed.
+  function reloadMessage(messageId) {
+    htmx.ajax("GET", `/message/${messageId}`, { target: "#content" });
+  }
+
+  window.annotateMessage = async (messageId) => {
+    const content = prompt("Annotation:")?.trim();
+    if (!content) return;
+
+    const path = `/api/messages/${messageId}/annotations`;
+    if (await callApi("POST", path, { content })) {
+      reloadMessage(messageId);
+    }
+  };
+
+  window.editAnnotation = async (el, annotationId, messageId) => {
+    const content = prompt("Annotation:", el.dataset.content)?.trim();
+    if (!content) return;
+
+    const path = `/api/annotations/${annotationId}`;
+    if (await callApi("PUT", path, { content })) {
+      reloadMessage(messageId);
+    }
+  };
+
+  window.deleteAnnotation = async (annotationId, messageId) => {
+    if (!confirm("Delete this annotation?")) return;
+
+    if (await callApi("DELETE", `/api/annotations/${annotationId}`)) {
+      reloadMessage(messageId);
+    }
   };
 
   window.onAvatarError = (imgEl) => {
@@ -45,6 +164,15 @@
     }
   });
 
+  // htmx doesn't swap error responses by default. Show error pages instead of
+  // silently ignoring the failed request.
+  document.body.addEventListener("htmx:beforeSwap", (evt) => {
+    if (evt.detail.xhr.getResponseHeader("X-Error-Page")) {
+      evt.detail.shouldSwap = true;
+      evt.detail.isError = false;
+    }
+  });
+
   document.body.addEventListener("htmx:afterSwap", (evt) => {
     const currentScrollContainer = getScrollContainer();
 
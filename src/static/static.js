@@ -0,0 +1,145 @@
+// Search for static exports, over the index written by `export html`.
+(() => {
+  const MAX_RESULTS = 200;
+
+  const input = document.querySelector("#static-search");
+  const content = document.querySelector("#content");
+  const pageTitle = document.querySelector("#page-title");
+  const root = input.dataset.root;
+
+  const originalContent = content.innerHTML;
+  const originalTitle = pageTitle.textContent;
+
+  // Like the server, show the latest messages of a channel first.
+  if (!window.location.hash) {
+    content.scrollTop = content.scrollHeight;
+  }
+
+  let indexLoaded = null;
+  // Incremented by each search, so that searches overtaken by a newer one stop.
+  let searchId = 0;
+
+  // Loads a script of the index, returning the value it assigns to
+  // `window[name]`. The index is made of scripts rather than JSON, as pages
+  // opened from the file system can't fetch files.
+  function loadScript(path, name) {
+    return new Promise((resolve, reject) => {
+      const script = document.createElement("script");
+      script.src = `${root}${path}`;
+      script.onload = () => {
+        const value = window[name];
+        delete window[name];
+        script.remove();
+        resolve(value);
+      };
+      script.onerror = () => reject(new Error("Couldn't load the search index"));
+      document.head.appendChild(script);
+    });
+  }
+
+  function loadIndex() {
+    if (!indexLoaded) {
+      indexLoaded = loadScript("search-index.js", "searchIndex");
+    }
+    return indexLoaded;
+  }
+
+  function byLatest(a, b) {
+    return a[5] < b[5] ? 1 : a[5] > b[5] ? -1 : 0;
+  }
+
+  function element(tag, className, text) {
+    const el = document.createElement(tag);
+    if (className) el.className = className;
+    if (text !== undefined) el.textContent = text;
+    return el;
+  }
+
+  function avatarUrl(avatar) {
+    return avatar.startsWith("/") ? root + avatar.slice(1) : avatar;
+  }
+
+  function renderResults(results) {
+    const list = element("ul", "messages");
+
+    if (results.length === 0) {
+      list.textContent = "No results found";
+      return list;
+    }
+
+    let previousUsername = null;
+    for (const [channelId, page, messageId, username, avatar, sentAt, text] of results) {
+      if (username !== previousUsername) {
+        const header = element("li", "username");
+
+        const avatarSpan = element("span", "avatar");
+        const img = element("img");
+        img.alt = "";
+        img.src = avatarUrl(avatar);
+        avatarSpan.appendChild(img);
+        header.appendChild(avatarSpan);
+
+        header.appendChild(element("span", "usr", username));
+        header.appendChild(element("span", "time", sentAt));
+
+        const jump = element("a", "jump-btn", "Jump");
+        jump.href = `${root}channel/${channelId}/${page}.html#message-${messageId}`;
+        header.appendChild(jump);
+
+        list.appendChild(header);
+        previousUsername = username;
+      }
+
+      list.appendChild(element("li", "msg", text));
+    }
+
+    return list;
+  }
+
+  async function search() {
+    const terms = input.value.toLowerCase().split(/\s+/).filter(Boolean);
+    const id = ++searchId;
+
+    if (terms.length === 0) {
+      searchId++;
+      content.innerHTML = originalContent;
+      pageTitle.textContent = originalTitle;
+      return;
+    }
+
+    pageTitle.textContent = "Search Results";
+    content.replaceChildren(element("p", null, "Searching..."));
+
+    // Shards are searched one at a time, keeping only the latest results.
+    let results = [];
+    try {
+      const index = await loadIndex();
+      for (const shard of index.shards) {
+        const messages = await loadScript(shard, "searchShard");
+        if (id !== searchId) return;
+
+        const matches = messages.filter((message) => {
+          const text = message[6].toLowerCase();
+          return terms.every((term) => text.includes(term));
+        });
+        results = results.concat(matches).sort(byLatest).slice(0, MAX_RESULTS);
+      }
+    } catch (e) {
+      if (id === searchId) {
+        content.replaceChildren(element("p", null, e.message));
+      }
+      return;
+    }
+    if (id !== searchId) return;
+
+    // The latest results are shown oldest first.
+    content.replaceChildren(renderResults(results.reverse()));
+    content.scrollTop = content.scrollHeight;
+  }
+
+  let timeout = null;
+  input.addEventListener("input", () => {
+    clearTimeout(timeout);
+    timeout = setTimeout(search, 300);
+  });
+})();
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of users listed by [`Stats::users`].
+pub const TOP_USERS: usize = 50;
+
+/// Number of users listed by [`ChannelStats::top_posters`].
+pub const TOP_POSTERS: usize = 5;
+
+/// Narrows down the statistics of the archive.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StatsQuery {
+    /// Only the messages of this channel.
+    pub channel: Option<u64>,
+    /// Only the messages sent during this year, in UTC.
+    pub year: Option<i32>,
+}
+
+impl StatsQuery {
+    /// The query string of the statistics pages, including the leading `?`
+    /// unless it's empty.
+    pub fn query_string(&self) -> String {
+        let params = [
+            self.channel.map(|channel| format!("channel={channel}")),
+            self.year.map(|year| format!("year={year}")),
+        ];
+        let params = params.into_iter().flatten().collect::<Vec<_>>();
+
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+/// A number of messages, e.g. of a user or during a month.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Count {
+    pub name: String,
+    pub messages: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChannelStats {
+    pub channel_id: u64,
+    pub name: String,
+    pub messages: u64,
+    /// The users who sent the most messages in the channel, at most
+    /// [`TOP_POSTERS`] of them.
+    pub top_posters: Vec<Count>,
+}
+
+/// Statistics of the messages matching a [`StatsQuery`], computed from the
+/// aggregate tables of the archive. Redacted messages aren't counted.
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub messages: u64,
+    /// The years with messages, regardless of the queried year.
+    pub years: Vec<i32>,
+    /// Channels with messages, the most active first.
+    pub channels: Vec<ChannelStats>,
+    /// The users who sent the most messages, at most [`TOP_USERS`] of them.
+    pub users: Vec<Count>,
+    /// Months with messages, as `YYYY-MM`, in chronological order.
+    pub months: Vec<Count>,
+    /// Number of messages sent on each weekday (from Sunday) at each hour, in
+    /// UTC.
+    pub activity: [[u64; 24]; 7],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_string() {
+        assert_eq!(StatsQuery::default().query_string(), "");
+        assert_eq!(
+            StatsQuery { year: Some(2019), ..Default::default() }.query_string(),
+            "?year=2019"
+        );
+        assert_eq!(
+            StatsQuery { channel: Some(3), year: Some(2019) }.query_string(),
+            "?channel=3&year=2019"
+        );
+    }
+}
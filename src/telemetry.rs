@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use serde::Deserialize;
+use tracing::field::Empty;
+use tracing::{info, info_span, Span};
+
+/// Histogram buckets, in seconds, for request and query durations.
+const DURATION_BUCKETS: &[f64] =
+    &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The `[metrics]` section of the configuration.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Users who can read `/metrics`, as user names, `@group` names, or `*`
+    /// for any authenticated user. Nobody can by default.
+    pub allow: Vec<String>,
+}
+
+/// Installs the global Prometheus recorder.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), DURATION_BUCKETS)?
+        .install_recorder()
+}
+
+fn route(request: &Request) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "fallback".to_string(), |path| path.as_str().to_string())
+}
+
+/// Creates the span that wraps the handling of a request.
+///
+/// The `status` and `latency_ms` fields are recorded by [`on_response`], and
+/// `db_ms` by [`record_db_time`].
+pub fn make_span(request: &Request) -> Span {
+    info_span!(
+        "request",
+        method = %request.method(),
+        route = route(request),
+        uri = %request.uri(),
+        status = Empty,
+        latency_ms = Empty,
+        db_ms = Empty,
+    )
+}
+
+/// Logs the completion of a request.
+pub fn on_response(response: &Response, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_secs_f64() * 1000.);
+    info!("finished processing request");
+}
+
+/// Records time spent in a database task in the current request's span and in
+/// the database metrics.
+pub fn record_db_time(elapsed: Duration) {
+    Span::current().record("db_ms", elapsed.as_secs_f64() * 1000.);
+    histogram!("amardiscord_db_task_duration_seconds").record(elapsed);
+}
+
+/// Middleware counting requests and measuring their latency per route.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = route(&request);
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    counter!(
+        "amardiscord_http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => status,
+    )
+    .increment(1);
+    histogram!(
+        "amardiscord_http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+    )
+    .record(start.elapsed());
+
+    response
+}
@@ -1,29 +1,145 @@
+use std::fmt::Display;
+
 use askama::Template;
 use itertools::Itertools;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
+use crate::annotations::MessageAnnotations;
+use crate::calendar::{self, CalendarMonth, Day};
+use crate::collections::{Collection, CollectionSummary, EntryContext, MAX_NAME_LENGTH};
+use crate::feed::Feed;
+use crate::index::{ArchiveSummary, RECENT_DAYS};
+use crate::links::{LinkPage, LinksQuery};
 use crate::search::SearchResult;
-use crate::{ChannelList, Message, ScrollDirection};
+use crate::stats::{Count, Stats, StatsQuery};
+use crate::user::{UserMessage, UserSummary};
+use crate::{ChannelList, ChannelListEntry, Message, ScrollDirection};
+
+/// Where the links of a page point to.
+#[derive(Clone, Copy)]
+pub enum Links<'a> {
+    /// The routes of the server.
+    Server,
+    /// The files of a static export, given the relative path from the page to
+    /// the root of the site (e.g. `../../`).
+    Static(&'a str),
+}
+
+impl Links<'_> {
+    pub fn is_static(&self) -> bool {
+        matches!(self, Links::Static(_))
+    }
+
+    pub fn root(&self) -> &str {
+        match self {
+            Links::Server => "/",
+            Links::Static(root) => root,
+        }
+    }
+
+    pub fn asset(&self, name: &str) -> String {
+        format!("{}{name}", self.root())
+    }
+
+    pub fn channel(&self, channel_id: impl Display, page: impl Display) -> String {
+        match self {
+            Links::Server => format!("/channel/{channel_id}/{page}"),
+            Links::Static(root) => format!("{root}channel/{channel_id}/{page}.html"),
+        }
+    }
+
+    /// User pages are only served by the server, so they aren't linked to
+    /// from static exports.
+    pub fn user(&self, username: &str) -> String {
+        format!("/user/{}", utf8_percent_encode(username, NON_ALPHANUMERIC))
+    }
+
+    /// Avatars served by the archive itself, such as identicons, are part of
+    /// the export too.
+    pub fn avatar(&self, avatar: &str) -> String {
+        match avatar.strip_prefix('/') {
+            Some(path) => self.asset(path),
+            None => avatar.to_string(),
+        }
+    }
+}
 
+/// The index page, summarising the archive and linking to the channel shown
+/// first.
 #[derive(Template)]
 #[template(path = "index.html")]
-pub struct IndexTemplate;
+pub struct IndexTemplate<'a> {
+    links: Links<'a>,
+    channels: Option<&'a str>,
+    name: Option<&'a str>,
+    summary: &'a ArchiveSummary,
+    default_channel: Option<&'a ChannelListEntry>,
+}
 
-impl IndexTemplate {
-    pub fn render() -> String {
-        Self {}.render().unwrap_or_else(|e| e.to_string())
+impl<'a> IndexTemplate<'a> {
+    pub fn render(
+        name: Option<&'a str>,
+        summary: &'a ArchiveSummary,
+        default_channel: Option<&'a ChannelListEntry>,
+    ) -> String {
+        IndexTemplate { links: Links::Server, channels: None, name, summary, default_channel }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
     }
 }
 
+/// An Atom feed, whose links are prefixed with the public URL of the server
+/// if it's configured, and relative otherwise.
+#[derive(Template)]
+#[template(path = "feed.xml")]
+pub struct FeedTemplate<'a> {
+    feed: &'a Feed,
+    id: String,
+    base_url: &'a str,
+}
+
+impl<'a> FeedTemplate<'a> {
+    pub fn render(feed: &'a Feed, base_url: Option<&'a str>) -> String {
+        let base_url = base_url.map(|url| url.trim_end_matches('/'));
+        Self { feed, id: feed.id(base_url), base_url: base_url.unwrap_or_default() }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+    }
+}
+
+/// A page of the archive, with the channel list loaded separately unless
+/// `channels` is already rendered.
 #[derive(Template)]
 #[template(path = "layout.html")]
 pub struct LayoutTemplate<'a> {
     title: &'a str,
     content: &'a str,
+    links: Links<'a>,
+    channels: Option<&'a str>,
 }
 
 impl<'a> LayoutTemplate<'a> {
-    pub fn render(title: &'a str, content: &'a str) -> String {
-        Self { title, content }.render().unwrap_or_else(|e| e.to_string())
+    pub fn render(
+        title: &'a str,
+        content: &'a str,
+        links: Links<'a>,
+        channels: Option<&'a str>,
+    ) -> String {
+        Self { title, content, links, channels }.render().unwrap_or_else(|e| e.to_string())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorTemplate<'a> {
+    status: u16,
+    title: &'a str,
+    message: &'a str,
+}
+
+impl<'a> ErrorTemplate<'a> {
+    pub fn render(status: u16, title: &'a str, message: &'a str) -> String {
+        Self { status, title, message }.render().unwrap_or_else(|e| e.to_string())
     }
 }
 
@@ -32,11 +148,16 @@ impl<'a> LayoutTemplate<'a> {
 pub struct ChannelListTemplate<'a> {
     channel_list: &'a ChannelList,
     current_channel_id: Option<u64>,
+    links: Links<'a>,
 }
 
 impl<'a> ChannelListTemplate<'a> {
-    pub fn render(channel_list: &'a ChannelList, current_channel_id: Option<u64>) -> String {
-        Self { channel_list, current_channel_id }.render().unwrap_or_else(|e| e.to_string())
+    pub fn render(
+        channel_list: &'a ChannelList,
+        current_channel_id: Option<u64>,
+        links: Links<'a>,
+    ) -> String {
+        Self { channel_list, current_channel_id, links }.render().unwrap_or_else(|e| e.to_string())
     }
 }
 
@@ -55,42 +176,95 @@ pub struct MessagePageTemplate<'a> {
     channel_name: String,
     page: u64,
     direction: ScrollDirection,
+    annotations: MessageAnnotations,
+    links: Links<'a>,
 }
 
-impl MessagePageTemplate<'_> {
+impl<'a> MessagePageTemplate<'a> {
+    /// Renders a page of messages, with a way to load the pages around it in
+    /// the given direction.
     pub fn render(
-        messages: &[Message],
+        messages: &'a [Message],
         channel_id: u64,
         channel_name: String,
         page: u64,
         direction: ScrollDirection,
         target_message_id: Option<u64>,
+        links: Links<'a>,
     ) -> String {
         if messages.is_empty() {
             String::new()
         } else {
-            let message_groups = messages
-                .iter()
-                .rev()
-                .group_by(|msg| &msg.username)
-                .into_iter()
-                .map(|(username, mut messages)| {
-                    let first_message = messages.next().unwrap();
-                    let messages = messages.collect::<Vec<_>>();
-                    let highlighted = target_message_id
-                        .map(|id| {
-                            first_message.rowid == id || messages.iter().any(|m| m.rowid == id)
-                        })
-                        .unwrap_or(false);
-                    MessageGroup { username, first_message, messages, highlighted }
-                })
-                .collect();
-
-            MessagePageTemplate { message_groups, channel_id, channel_name, page, direction }
+            Self::new(messages, channel_id, channel_name, page, direction, target_message_id, links)
                 .render()
                 .unwrap_or_else(|e| e.to_string())
         }
     }
+
+    /// Renders a page of messages served along with their annotations.
+    pub fn render_annotated(
+        messages: &'a [Message],
+        channel_id: u64,
+        channel_name: String,
+        page: u64,
+        direction: ScrollDirection,
+        target_message_id: Option<u64>,
+        annotations: MessageAnnotations,
+    ) -> String {
+        if messages.is_empty() {
+            String::new()
+        } else {
+            Self {
+                annotations,
+                ..Self::new(
+                    messages,
+                    channel_id,
+                    channel_name,
+                    page,
+                    direction,
+                    target_message_id,
+                    Links::Server,
+                )
+            }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+        }
+    }
+
+    fn new(
+        messages: &'a [Message],
+        channel_id: u64,
+        channel_name: String,
+        page: u64,
+        direction: ScrollDirection,
+        target_message_id: Option<u64>,
+        links: Links<'a>,
+    ) -> Self {
+        let message_groups = messages
+            .iter()
+            .rev()
+            .group_by(|msg| &msg.username)
+            .into_iter()
+            .map(|(username, mut messages)| {
+                let first_message = messages.next().unwrap();
+                let messages = messages.collect::<Vec<_>>();
+                let highlighted = target_message_id
+                    .map(|id| first_message.rowid == id || messages.iter().any(|m| m.rowid == id))
+                    .unwrap_or(false);
+                MessageGroup { username, first_message, messages, highlighted }
+            })
+            .collect();
+
+        MessagePageTemplate {
+            message_groups,
+            channel_id,
+            channel_name,
+            page,
+            direction,
+            annotations: MessageAnnotations::default(),
+            links,
+        }
+    }
 }
 
 struct SearchResultGroup<'a> {
@@ -103,10 +277,12 @@ struct SearchResultGroup<'a> {
 #[template(path = "search.html")]
 pub struct SearchTemplate<'a> {
     search_result_groups: Vec<SearchResultGroup<'a>>,
+    annotations: &'a MessageAnnotations,
+    links: Links<'a>,
 }
 
-impl SearchTemplate<'_> {
-    pub fn render(search_results: &[SearchResult]) -> String {
+impl<'a> SearchTemplate<'a> {
+    pub fn render(search_results: &[SearchResult], annotations: &'a MessageAnnotations) -> String {
         SearchTemplate {
             search_result_groups: search_results
                 .iter()
@@ -119,8 +295,206 @@ impl SearchTemplate<'_> {
                     SearchResultGroup { username, first_search_result, search_results }
                 })
                 .collect(),
+            annotations,
+            links: Links::Server,
         }
         .render()
         .unwrap_or_else(|e| e.to_string())
     }
 }
+
+#[derive(Template)]
+#[template(path = "user.html")]
+pub struct UserTemplate<'a> {
+    summary: &'a UserSummary,
+    messages: &'a [UserMessage],
+    page: u64,
+    links: Links<'a>,
+}
+
+impl<'a> UserTemplate<'a> {
+    /// Renders the summary of a user, followed by a page of their messages.
+    pub fn render(summary: &'a UserSummary, messages: &'a [UserMessage], page: u64) -> String {
+        UserTemplate { summary, messages, page, links: Links::Server }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+    }
+
+    fn page_link(&self, page: u64) -> String {
+        format!("{}?page={page}", self.links.user(&self.summary.username))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "calendar.html")]
+pub struct CalendarTemplate<'a> {
+    channel_id: u64,
+    channel_name: &'a str,
+    // Number of messages of each year.
+    years: &'a [(i32, u64)],
+    year: Option<i32>,
+    months: Vec<CalendarMonth>,
+    // Number of messages of the busiest day of the year.
+    max_day: u64,
+}
+
+impl<'a> CalendarTemplate<'a> {
+    /// Renders the calendar of a year of a channel, given the days of that
+    /// year with messages.
+    pub fn render(
+        channel_id: u64,
+        channel_name: &'a str,
+        years: &'a [(i32, u64)],
+        year: Option<i32>,
+        days: &[Day],
+    ) -> String {
+        let max_day = days.iter().map(|day| day.messages).max().unwrap_or(0).max(1);
+
+        CalendarTemplate {
+            channel_id,
+            channel_name,
+            years,
+            year,
+            months: calendar::months(days),
+            max_day,
+        }
+        .render()
+        .unwrap_or_else(|e| e.to_string())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "links.html")]
+pub struct LinksTemplate<'a> {
+    page: &'a LinkPage,
+    query: &'a LinksQuery,
+    links: Links<'a>,
+}
+
+impl<'a> LinksTemplate<'a> {
+    pub fn render(page: &'a LinkPage, query: &'a LinksQuery) -> String {
+        LinksTemplate { page, query, links: Links::Server }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+    }
+
+    // Links to the first page of the links of another domain or channel, or to
+    // another page.
+    fn with_domain(&self, domain: Option<&String>) -> String {
+        let domain = domain.cloned();
+        format!("/links{}", LinksQuery { domain, page: 0, ..self.query.clone() }.query_string())
+    }
+
+    fn with_channel(&self, channel: Option<&u64>) -> String {
+        let channel = channel.copied();
+        format!("/links{}", LinksQuery { channel, page: 0, ..self.query.clone() }.query_string())
+    }
+
+    fn with_page(&self, page: u64) -> String {
+        format!("/links{}", LinksQuery { page, ..self.query.clone() }.query_string())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "collections.html")]
+pub struct CollectionsTemplate<'a> {
+    collections: &'a [CollectionSummary],
+}
+
+impl<'a> CollectionsTemplate<'a> {
+    /// Renders the collections of the viewer.
+    pub fn render(collections: &'a [CollectionSummary]) -> String {
+        CollectionsTemplate { collections }.render().unwrap_or_else(|e| e.to_string())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "collection.html")]
+pub struct CollectionTemplate<'a> {
+    collection: &'a Collection,
+    entries: &'a [EntryContext],
+    // Ids of the entries hidden from the viewer or removed from the archive.
+    hidden: &'a [u64],
+    is_owner: bool,
+    links: Links<'a>,
+}
+
+impl<'a> CollectionTemplate<'a> {
+    pub fn render(
+        collection: &'a Collection,
+        entries: &'a [EntryContext],
+        hidden: &'a [u64],
+        is_owner: bool,
+    ) -> String {
+        CollectionTemplate { collection, entries, hidden, is_owner, links: Links::Server }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+pub struct StatsTemplate<'a> {
+    stats: &'a Stats,
+    query: &'a StatsQuery,
+    // Number of messages of each weekday and hour, along with its percentage
+    // of the busiest hour.
+    heatmap: Vec<(&'static str, Vec<(u64, u64)>)>,
+    // Each month, along with its percentage of the busiest month.
+    months: Vec<(&'a Count, u64)>,
+    links: Links<'a>,
+}
+
+impl<'a> StatsTemplate<'a> {
+    pub fn render(stats: &'a Stats, query: &'a StatsQuery) -> String {
+        let max_activity = stats.activity.iter().flatten().copied().max().unwrap_or(0).max(1);
+        let heatmap = WEEKDAYS
+            .into_iter()
+            .zip(&stats.activity)
+            .map(|(weekday, hours)| {
+                let hours = hours.iter().map(|&n| (n, n * 100 / max_activity)).collect();
+                (weekday, hours)
+            })
+            .collect();
+
+        let max_month = stats.months.iter().map(|month| month.messages).max().unwrap_or(0).max(1);
+        let months =
+            stats.months.iter().map(|month| (month, month.messages * 100 / max_month)).collect();
+
+        StatsTemplate { stats, query, heatmap, months, links: Links::Server }
+            .render()
+            .unwrap_or_else(|e| e.to_string())
+    }
+
+    // Links to the statistics of another year or channel.
+    fn with_year(&self, year: Option<&i32>) -> String {
+        let year = year.copied();
+        format!("/stats{}", StatsQuery { year, ..self.query.clone() }.query_string())
+    }
+
+    fn with_channel(&self, channel: Option<&u64>) -> String {
+        let channel = channel.copied();
+        format!("/stats{}", StatsQuery { channel, ..self.query.clone() }.query_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links() {
+        let links = Links::Static("../../");
+
+        assert_eq!(links.channel(1, 2), "../../channel/1/2.html");
+        assert_eq!(links.asset("index.css"), "../../index.css");
+        assert_eq!(links.avatar("/identicon/abc.svg"), "../../identicon/abc.svg");
+        assert_eq!(links.avatar("https://cdn/a.png"), "https://cdn/a.png");
+
+        assert_eq!(Links::Server.channel(1, 2), "/channel/1/2");
+        assert_eq!(Links::Server.avatar("/identicon/abc.svg"), "/identicon/abc.svg");
+        assert_eq!(Links::Server.user("Shy Lynx #9062"), "/user/Shy%20Lynx%20%239062");
+    }
+}
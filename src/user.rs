@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::Message;
+
+/// Which page of the messages of a user is shown.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UserQuery {
+    #[serde(default)]
+    pub page: u64,
+}
+
+/// Number of messages of a user in a channel.
+pub struct UserChannel {
+    pub channel_id: u64,
+    pub name: String,
+    pub messages: u64,
+}
+
+/// Activity of a user across the channels they wrote in.
+pub struct UserSummary {
+    pub username: String,
+    pub messages: u64,
+    pub first_sent_at: DateTime<Utc>,
+    pub last_sent_at: DateTime<Utc>,
+    /// Channels the user wrote in, the most active first.
+    pub channels: Vec<UserChannel>,
+    /// Number of pages of messages.
+    pub pages: u64,
+}
+
+/// A message of a user, along with the channel it was sent in.
+pub struct UserMessage {
+    pub channel_id: u64,
+    pub channel_name: String,
+    pub message: Message,
+}
@@ -0,0 +1,59 @@
+-- An archive as built with schema version 1. Kept as it was rather than
+-- derived from the migrations, so that migrating it tests real archives.
+
+CREATE TABLE categories (
+    category_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE channels (
+    channel_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    channel_type INTEGER NOT NULL,
+    name TEXT,
+    category_id INTEGER NOT NULL,
+    FOREIGN KEY(category_id) REFERENCES categories(category_id)
+);
+
+CREATE TABLE messages (
+    rowid INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    content TEXT NOT NULL,
+    username TEXT NOT NULL,
+    avatar TEXT NOT NULL,
+    sent_at TEXT NOT NULL,
+    channel_id INTEGER NOT NULL,
+    FOREIGN KEY(channel_id) REFERENCES channels(channel_id)
+);
+
+CREATE VIRTUAL TABLE messages_fts
+USING FTS5(content, username, avatar, messages_rowid);
+
+CREATE TABLE messages_pages (
+    messages_rowid INTEGER NOT NULL PRIMARY KEY,
+    channel_id INTEGER NOT NULL,
+    page INTEGER NOT NULL,
+    FOREIGN KEY(messages_rowid) REFERENCES messages(rowid)
+);
+
+CREATE TABLE metadata (
+    key TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE schema_version (
+    version INTEGER NOT NULL PRIMARY KEY,
+    applied_at TEXT NOT NULL
+);
+
+INSERT INTO schema_version (version, applied_at) VALUES (1, '2020-01-01T00:00:00+00:00');
+
+INSERT INTO categories (category_id, name) VALUES (1, 'Category');
+INSERT INTO channels (channel_id, channel_type, name, category_id)
+VALUES (1, 0, 'general', 1);
+INSERT INTO messages (content, username, avatar, sent_at, channel_id)
+VALUES ('hello https://example.com', 'user', '', '2020-01-01T00:00:00Z', 1);
+INSERT INTO messages_fts (content, username, avatar, messages_rowid)
+VALUES ('hello https://example.com', 'user', '', 1);
+INSERT INTO messages_pages (messages_rowid, channel_id, page) VALUES (1, 1, 0);
+INSERT INTO metadata (key, value) VALUES
+    ('source_fingerprint', 'abc'),
+    ('built_at', '2020-01-01T00:00:00Z');